@@ -9801,6 +9801,15 @@ rec {
             name = "anyhow";
             packageId = "anyhow";
           }
+          {
+            name = "chrono";
+            packageId = "chrono";
+          }
+          {
+            name = "clap";
+            packageId = "clap";
+            features = [ "derive" ];
+          }
           {
             name = "dotenvy";
             packageId = "dotenvy";
@@ -9821,6 +9830,10 @@ rec {
             name = "grammers-session";
             packageId = "grammers-session";
           }
+          {
+            name = "grammers-tl-types";
+            packageId = "grammers-tl-types";
+          }
           {
             name = "log";
             packageId = "log";
@@ -9838,11 +9851,28 @@ rec {
             packageId = "moka";
             features = [ "future" ];
           }
+          {
+            name = "regex";
+            packageId = "regex";
+          }
           {
             name = "sea-orm";
             packageId = "sea-orm";
             features = [ "sqlx-sqlite" "runtime-tokio-native-tls" "macros" ];
           }
+          {
+            name = "serde";
+            packageId = "serde";
+            features = [ "derive" ];
+          }
+          {
+            name = "serde_json";
+            packageId = "serde_json";
+          }
+          {
+            name = "sha2";
+            packageId = "sha2";
+          }
           {
             name = "shellexpand";
             packageId = "shellexpand";
@@ -17,6 +17,27 @@ pub struct Model {
     pub binary_data_type: Option<String>,
     pub date: String,
     pub binary_data_reference_expired: bool,
+    pub chat_kind: String,
+    pub post_author: Option<String>,
+    pub view_count: Option<i32>,
+    pub forward_count: Option<i32>,
+    pub edit_date: Option<String>,
+    pub edit_count: i32,
+    pub media_file_id: Option<i64>,
+    pub deleted_at: Option<String>,
+    pub deleted_offline: bool,
+    pub reply_to_message_id: Option<i32>,
+    pub forward_from_id: Option<i64>,
+    pub forward_from_name: Option<String>,
+    pub forward_date: Option<String>,
+    pub forward_message_id: Option<i32>,
+    pub entities: Option<String>,
+    pub binary_data_skipped: bool,
+    pub media_id: Option<i32>,
+    pub grouped_id: Option<i64>,
+    pub media_data: Option<String>,
+    pub web_page: Option<String>,
+    pub binary_data_attempted_at: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -2,4 +2,9 @@
 
 pub mod prelude;
 
+pub mod chats;
+pub mod media;
+pub mod message_revisions;
 pub mod messages;
+pub mod user_revisions;
+pub mod users;
@@ -1,3 +1,8 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.11.3
 
+pub use super::chats::Entity as Chats;
+pub use super::media::Entity as Media;
+pub use super::message_revisions::Entity as MessageRevisions;
 pub use super::messages::Entity as Messages;
+pub use super::user_revisions::Entity as UserRevisions;
+pub use super::users::Entity as Users;
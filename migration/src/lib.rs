@@ -2,6 +2,25 @@ pub use sea_orm_migration::prelude::*;
 
 mod m20231027_132357_init;
 mod m20231030_091220_create_binary_reference_expired;
+mod m20261018_100000_add_chat_kind;
+mod m20261018_110000_add_channel_post_stats;
+mod m20261018_120000_create_message_revisions;
+mod m20261018_130000_add_deleted_at;
+mod m20261018_140000_add_deleted_offline;
+mod m20261018_150000_create_users_and_chats;
+mod m20261018_160000_create_user_revisions;
+mod m20261018_170000_add_reply_and_forward_info;
+mod m20261018_180000_add_message_entities;
+mod m20261018_190000_add_binary_data_skipped;
+mod m20261018_200000_create_media;
+mod m20261018_210000_add_media_metadata;
+mod m20261018_220000_add_grouped_id;
+mod m20261018_230000_add_media_data;
+mod m20261018_240000_add_web_page;
+mod m20261018_250000_create_messages_fts;
+mod m20261018_260000_add_profile_photo_media;
+mod m20261018_270000_add_binary_data_attempted_at;
+mod m20261018_280000_key_messages_fts_by_message;
 
 pub struct Migrator;
 
@@ -11,6 +30,25 @@ impl MigratorTrait for Migrator {
         vec![
             Box::new(m20231027_132357_init::Migration),
             Box::new(m20231030_091220_create_binary_reference_expired::Migration),
+            Box::new(m20261018_100000_add_chat_kind::Migration),
+            Box::new(m20261018_110000_add_channel_post_stats::Migration),
+            Box::new(m20261018_120000_create_message_revisions::Migration),
+            Box::new(m20261018_130000_add_deleted_at::Migration),
+            Box::new(m20261018_140000_add_deleted_offline::Migration),
+            Box::new(m20261018_150000_create_users_and_chats::Migration),
+            Box::new(m20261018_160000_create_user_revisions::Migration),
+            Box::new(m20261018_170000_add_reply_and_forward_info::Migration),
+            Box::new(m20261018_180000_add_message_entities::Migration),
+            Box::new(m20261018_190000_add_binary_data_skipped::Migration),
+            Box::new(m20261018_200000_create_media::Migration),
+            Box::new(m20261018_210000_add_media_metadata::Migration),
+            Box::new(m20261018_220000_add_grouped_id::Migration),
+            Box::new(m20261018_230000_add_media_data::Migration),
+            Box::new(m20261018_240000_add_web_page::Migration),
+            Box::new(m20261018_250000_create_messages_fts::Migration),
+            Box::new(m20261018_260000_add_profile_photo_media::Migration),
+            Box::new(m20261018_270000_add_binary_data_attempted_at::Migration),
+            Box::new(m20261018_280000_key_messages_fts_by_message::Migration),
         ]
     }
 }
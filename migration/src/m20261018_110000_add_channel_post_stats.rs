@@ -0,0 +1,62 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // SQLite only supports a single column per ALTER TABLE statement
+        manager
+            .alter_table(
+                TableAlterStatement::new()
+                    .table(Messages::Table)
+                    .add_column(ColumnDef::new(Messages::PostAuthor).text())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                TableAlterStatement::new()
+                    .table(Messages::Table)
+                    .add_column(ColumnDef::new(Messages::ViewCount).integer())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                TableAlterStatement::new()
+                    .table(Messages::Table)
+                    .add_column(ColumnDef::new(Messages::ForwardCount).integer())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in [
+            Messages::PostAuthor,
+            Messages::ViewCount,
+            Messages::ForwardCount,
+        ] {
+            manager
+                .alter_table(
+                    TableAlterStatement::new()
+                        .table(Messages::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Messages {
+    Table,
+    PostAuthor,
+    ViewCount,
+    ForwardCount,
+}
@@ -0,0 +1,149 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                TableAlterStatement::new()
+                    .table(Messages::Table)
+                    .add_column(ColumnDef::new(Messages::EditDate).date_time())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                TableAlterStatement::new()
+                    .table(Messages::Table)
+                    .add_column(
+                        ColumnDef::new(Messages::EditCount)
+                            .integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                TableAlterStatement::new()
+                    .table(Messages::Table)
+                    .add_column(ColumnDef::new(Messages::MediaFileId).big_integer())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(MessageRevisions::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(MessageRevisions::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(MessageRevisions::MessageId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(MessageRevisions::ChatId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(MessageRevisions::Revision)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(MessageRevisions::Text).text().not_null())
+                    .col(
+                        ColumnDef::new(MessageRevisions::Date)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(MessageRevisions::ReplacedAt).date_time())
+                    .col(
+                        ColumnDef::new(MessageRevisions::HasBinaryData)
+                            .boolean()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(MessageRevisions::BinaryDataPath).text())
+                    .col(ColumnDef::new(MessageRevisions::BinaryDataType).text())
+                    .col(ColumnDef::new(MessageRevisions::MediaFileId).big_integer())
+                    .col(
+                        ColumnDef::new(MessageRevisions::MediaChanged)
+                            .boolean()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-message_revisions-message_id_chat_id")
+                    .table(MessageRevisions::Table)
+                    .col(MessageRevisions::MessageId)
+                    .col(MessageRevisions::ChatId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(MessageRevisions::Table).to_owned())
+            .await?;
+
+        for column in [
+            Messages::EditDate,
+            Messages::EditCount,
+            Messages::MediaFileId,
+        ] {
+            manager
+                .alter_table(
+                    TableAlterStatement::new()
+                        .table(Messages::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Messages {
+    Table,
+    EditDate,
+    EditCount,
+    MediaFileId,
+}
+
+#[derive(DeriveIden)]
+enum MessageRevisions {
+    Table,
+    Id,
+    MessageId,
+    ChatId,
+    Revision,
+    Text,
+    Date,
+    ReplacedAt,
+    HasBinaryData,
+    BinaryDataPath,
+    BinaryDataType,
+    MediaFileId,
+    MediaChanged,
+}
@@ -0,0 +1,76 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Users::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Users::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Users::FirstName).text().not_null())
+                    .col(ColumnDef::new(Users::LastName).text())
+                    .col(ColumnDef::new(Users::Username).text())
+                    .col(ColumnDef::new(Users::Phone).text())
+                    .col(ColumnDef::new(Users::IsBot).boolean().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(Chats::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Chats::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Chats::Kind).text().not_null())
+                    .col(ColumnDef::new(Chats::Title).text().not_null())
+                    .col(ColumnDef::new(Chats::Username).text())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Chats::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Users::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Users {
+    Table,
+    Id,
+    FirstName,
+    LastName,
+    Username,
+    Phone,
+    IsBot,
+}
+
+#[derive(DeriveIden)]
+enum Chats {
+    Table,
+    Id,
+    Kind,
+    Title,
+    Username,
+}
@@ -0,0 +1,110 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                TableAlterStatement::new()
+                    .table(Users::Table)
+                    .add_column(ColumnDef::new(Users::PhotoId).big_integer())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                TableAlterStatement::new()
+                    .table(Users::Table)
+                    .add_column(ColumnDef::new(Users::Packed).binary())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(UserRevisions::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(UserRevisions::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(UserRevisions::UserId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(UserRevisions::Version).integer().not_null())
+                    .col(ColumnDef::new(UserRevisions::FirstName).text().not_null())
+                    .col(ColumnDef::new(UserRevisions::LastName).text())
+                    .col(ColumnDef::new(UserRevisions::Username).text())
+                    .col(ColumnDef::new(UserRevisions::PhotoId).big_integer())
+                    .col(ColumnDef::new(UserRevisions::PhotoPath).text())
+                    .col(
+                        ColumnDef::new(UserRevisions::PhotoDownloaded)
+                            .boolean()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(UserRevisions::SeenAt).date_time().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-user_revisions-user_id")
+                    .table(UserRevisions::Table)
+                    .col(UserRevisions::UserId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(UserRevisions::Table).to_owned())
+            .await?;
+
+        for column in [Users::PhotoId, Users::Packed] {
+            manager
+                .alter_table(
+                    TableAlterStatement::new()
+                        .table(Users::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Users {
+    Table,
+    PhotoId,
+    Packed,
+}
+
+#[derive(DeriveIden)]
+enum UserRevisions {
+    Table,
+    Id,
+    UserId,
+    Version,
+    FirstName,
+    LastName,
+    Username,
+    PhotoId,
+    PhotoPath,
+    PhotoDownloaded,
+    SeenAt,
+}
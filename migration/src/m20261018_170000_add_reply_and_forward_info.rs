@@ -0,0 +1,65 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in [
+            ColumnDef::new(Messages::ReplyToMessageId)
+                .integer()
+                .to_owned(),
+            ColumnDef::new(Messages::ForwardFromId)
+                .big_integer()
+                .to_owned(),
+            ColumnDef::new(Messages::ForwardFromName).text().to_owned(),
+            ColumnDef::new(Messages::ForwardDate).date_time().to_owned(),
+            ColumnDef::new(Messages::ForwardMessageId)
+                .integer()
+                .to_owned(),
+        ] {
+            manager
+                .alter_table(
+                    TableAlterStatement::new()
+                        .table(Messages::Table)
+                        .add_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in [
+            Messages::ReplyToMessageId,
+            Messages::ForwardFromId,
+            Messages::ForwardFromName,
+            Messages::ForwardDate,
+            Messages::ForwardMessageId,
+        ] {
+            manager
+                .alter_table(
+                    TableAlterStatement::new()
+                        .table(Messages::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Messages {
+    Table,
+    ReplyToMessageId,
+    ForwardFromId,
+    ForwardFromName,
+    ForwardDate,
+    ForwardMessageId,
+}
@@ -0,0 +1,235 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // SQLite cannot relax NOT NULL constraints, so the table is rebuilt
+        manager
+            .rename_table(
+                Table::rename()
+                    .table(Media::Table, MediaBlobs::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(Media::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Media::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Media::FileKey).text())
+                    .col(ColumnDef::new(Media::FileId).big_integer())
+                    .col(ColumnDef::new(Media::Kind).text())
+                    .col(ColumnDef::new(Media::MimeType).text())
+                    .col(ColumnDef::new(Media::FileName).text())
+                    .col(ColumnDef::new(Media::Size).big_integer())
+                    .col(ColumnDef::new(Media::Width).integer())
+                    .col(ColumnDef::new(Media::Height).integer())
+                    .col(ColumnDef::new(Media::Duration).integer())
+                    .col(ColumnDef::new(Media::Sha256).text())
+                    .col(ColumnDef::new(Media::Path).text())
+                    .col(
+                        ColumnDef::new(Media::State)
+                            .text()
+                            .not_null()
+                            .default("pending"),
+                    )
+                    .col(
+                        ColumnDef::new(Media::Attempts)
+                            .integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(ColumnDef::new(Media::LastError).text())
+                    .col(
+                        ColumnDef::new(Media::RefCount)
+                            .integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        let db = manager.get_connection();
+
+        db.execute_unprepared(
+            "INSERT INTO media (id, file_key, file_id, kind, sha256, path, size, state, attempts, ref_count)
+             SELECT id, file_key,
+                    CAST(substr(file_key, instr(file_key, '-') + 1) AS INTEGER),
+                    substr(file_key, 1, instr(file_key, '-') - 1),
+                    sha256, path, size, 'downloaded', 1, ref_count
+             FROM media_blobs",
+        )
+        .await?;
+
+        manager
+            .drop_table(Table::drop().table(MediaBlobs::Table).to_owned())
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-media-file_key")
+                    .table(Media::Table)
+                    .col(Media::FileKey)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-media-sha256")
+                    .table(Media::Table)
+                    .col(Media::Sha256)
+                    .to_owned(),
+            )
+            .await?;
+
+        // Media archived before the media table existed gets a row per message, keyed
+        // temporarily so the rows can be linked back to their messages
+        db.execute_unprepared(
+            "INSERT INTO media (file_key, file_id, path, state, attempts, ref_count)
+             SELECT 'legacy-' || chat_id || '-' || id,
+                    media_file_id,
+                    binary_data_path,
+                    CASE
+                        WHEN binary_data_reference_expired THEN 'expired'
+                        WHEN binary_data_skipped THEN 'skipped'
+                        WHEN binary_data_downloaded AND binary_data_path IS NOT NULL THEN 'downloaded'
+                        WHEN binary_data_downloaded THEN 'unavailable'
+                        ELSE 'pending'
+                    END,
+                    CASE WHEN binary_data_downloaded THEN 1 ELSE 0 END,
+                    1
+             FROM messages
+             WHERE has_binary_data AND media_id IS NULL",
+        )
+        .await?;
+        db.execute_unprepared(
+            "UPDATE messages
+             SET media_id = (
+                 SELECT media.id FROM media
+                 WHERE media.file_key = 'legacy-' || messages.chat_id || '-' || messages.id
+             )
+             WHERE has_binary_data AND media_id IS NULL",
+        )
+        .await?;
+        db.execute_unprepared("UPDATE media SET file_key = NULL WHERE file_key LIKE 'legacy-%'")
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .rename_table(
+                Table::rename()
+                    .table(Media::Table, MediaBlobs::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(Media::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Media::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Media::FileKey).text())
+                    .col(ColumnDef::new(Media::Sha256).text().not_null())
+                    .col(ColumnDef::new(Media::Path).text().not_null())
+                    .col(ColumnDef::new(Media::Size).big_integer().not_null())
+                    .col(
+                        ColumnDef::new(Media::RefCount)
+                            .integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Only stored blobs survive, metadata and download state are lost
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            "INSERT OR IGNORE INTO media (id, file_key, sha256, path, size, ref_count)
+             SELECT id, file_key, sha256, path, size, ref_count
+             FROM media_blobs
+             WHERE state = 'downloaded' AND sha256 IS NOT NULL AND path IS NOT NULL AND size IS NOT NULL",
+        )
+        .await?;
+        db.execute_unprepared(
+            "UPDATE messages SET media_id = NULL WHERE media_id NOT IN (SELECT id FROM media)",
+        )
+        .await?;
+
+        manager
+            .drop_table(Table::drop().table(MediaBlobs::Table).to_owned())
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-media-file_key")
+                    .table(Media::Table)
+                    .col(Media::FileKey)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-media-sha256")
+                    .table(Media::Table)
+                    .col(Media::Sha256)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Media {
+    Table,
+    Id,
+    FileKey,
+    FileId,
+    Kind,
+    MimeType,
+    FileName,
+    Size,
+    Width,
+    Height,
+    Duration,
+    Sha256,
+    Path,
+    State,
+    Attempts,
+    LastError,
+    RefCount,
+}
+
+#[derive(DeriveIden)]
+enum MediaBlobs {
+    Table,
+}
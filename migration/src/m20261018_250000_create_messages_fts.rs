@@ -0,0 +1,64 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // The index only holds the tokens, text is read from `messages` by rowid
+        db.execute_unprepared(
+            "CREATE VIRTUAL TABLE messages_fts USING fts5(
+                 text,
+                 content = 'messages',
+                 content_rowid = 'rowid',
+                 tokenize = 'unicode61 remove_diacritics 2'
+             )",
+        )
+        .await?;
+
+        db.execute_unprepared(
+            "CREATE TRIGGER messages_fts_insert AFTER INSERT ON messages BEGIN
+                 INSERT INTO messages_fts (rowid, text) VALUES (new.rowid, new.text);
+             END",
+        )
+        .await?;
+        db.execute_unprepared(
+            "CREATE TRIGGER messages_fts_delete AFTER DELETE ON messages BEGIN
+                 INSERT INTO messages_fts (messages_fts, rowid, text)
+                 VALUES ('delete', old.rowid, old.text);
+             END",
+        )
+        .await?;
+        db.execute_unprepared(
+            "CREATE TRIGGER messages_fts_update AFTER UPDATE OF text ON messages BEGIN
+                 INSERT INTO messages_fts (messages_fts, rowid, text)
+                 VALUES ('delete', old.rowid, old.text);
+                 INSERT INTO messages_fts (rowid, text) VALUES (new.rowid, new.text);
+             END",
+        )
+        .await?;
+
+        db.execute_unprepared("INSERT INTO messages_fts (messages_fts) VALUES ('rebuild')")
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        db.execute_unprepared("DROP TRIGGER IF EXISTS messages_fts_update")
+            .await?;
+        db.execute_unprepared("DROP TRIGGER IF EXISTS messages_fts_delete")
+            .await?;
+        db.execute_unprepared("DROP TRIGGER IF EXISTS messages_fts_insert")
+            .await?;
+        db.execute_unprepared("DROP TABLE IF EXISTS messages_fts")
+            .await?;
+
+        Ok(())
+    }
+}
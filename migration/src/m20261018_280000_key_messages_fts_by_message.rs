@@ -0,0 +1,112 @@
+use sea_orm_migration::prelude::*;
+
+use crate::m20261018_250000_create_messages_fts;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        drop_messages_fts(manager).await?;
+
+        // `messages` has no integer primary key, so its implicit rowid may change on VACUUM.
+        // Index rows get an explicit key instead and carry the message key to join on.
+        db.execute_unprepared(
+            "CREATE TABLE messages_fts_keys (
+                 id INTEGER PRIMARY KEY,
+                 chat_id INTEGER NOT NULL,
+                 message_id INTEGER NOT NULL,
+                 UNIQUE (chat_id, message_id)
+             )",
+        )
+        .await?;
+        db.execute_unprepared(
+            "CREATE VIRTUAL TABLE messages_fts USING fts5(
+                 text,
+                 chat_id UNINDEXED,
+                 message_id UNINDEXED,
+                 tokenize = 'unicode61 remove_diacritics 2'
+             )",
+        )
+        .await?;
+
+        db.execute_unprepared(
+            "CREATE TRIGGER messages_fts_insert AFTER INSERT ON messages BEGIN
+                 INSERT INTO messages_fts_keys (chat_id, message_id) VALUES (new.chat_id, new.id);
+                 INSERT INTO messages_fts (rowid, text, chat_id, message_id)
+                 VALUES (
+                     (SELECT id FROM messages_fts_keys
+                      WHERE chat_id = new.chat_id AND message_id = new.id),
+                     new.text, new.chat_id, new.id
+                 );
+             END",
+        )
+        .await?;
+        db.execute_unprepared(
+            "CREATE TRIGGER messages_fts_delete AFTER DELETE ON messages BEGIN
+                 DELETE FROM messages_fts WHERE rowid = (
+                     SELECT id FROM messages_fts_keys
+                     WHERE chat_id = old.chat_id AND message_id = old.id
+                 );
+                 DELETE FROM messages_fts_keys
+                 WHERE chat_id = old.chat_id AND message_id = old.id;
+             END",
+        )
+        .await?;
+        db.execute_unprepared(
+            "CREATE TRIGGER messages_fts_update AFTER UPDATE OF text ON messages BEGIN
+                 UPDATE messages_fts SET text = new.text WHERE rowid = (
+                     SELECT id FROM messages_fts_keys
+                     WHERE chat_id = old.chat_id AND message_id = old.id
+                 );
+             END",
+        )
+        .await?;
+
+        db.execute_unprepared(
+            "INSERT INTO messages_fts_keys (chat_id, message_id) SELECT chat_id, id FROM messages",
+        )
+        .await?;
+        db.execute_unprepared(
+            "INSERT INTO messages_fts (rowid, text, chat_id, message_id)
+             SELECT messages_fts_keys.id, messages.text, messages.chat_id, messages.id
+             FROM messages_fts_keys
+             JOIN messages ON messages.chat_id = messages_fts_keys.chat_id
+                 AND messages.id = messages_fts_keys.message_id",
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        drop_messages_fts(manager).await?;
+
+        manager
+            .get_connection()
+            .execute_unprepared("DROP TABLE IF EXISTS messages_fts_keys")
+            .await?;
+
+        m20261018_250000_create_messages_fts::Migration
+            .up(manager)
+            .await
+    }
+}
+
+async fn drop_messages_fts(manager: &SchemaManager) -> Result<(), DbErr> {
+    let db = manager.get_connection();
+
+    db.execute_unprepared("DROP TRIGGER IF EXISTS messages_fts_update")
+        .await?;
+    db.execute_unprepared("DROP TRIGGER IF EXISTS messages_fts_delete")
+        .await?;
+    db.execute_unprepared("DROP TRIGGER IF EXISTS messages_fts_insert")
+        .await?;
+    db.execute_unprepared("DROP TABLE IF EXISTS messages_fts")
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,48 @@
+//! Albums arrive as separate messages sharing a `grouped_id`, these helpers put them back
+//! together into a single post.
+
+pub struct Post {
+    pub messages: Vec<entity::messages::Model>,
+}
+
+impl Post {
+    pub fn first(&self) -> &entity::messages::Model {
+        &self.messages[0]
+    }
+
+    pub fn is_album(&self) -> bool {
+        self.messages.len() > 1
+    }
+
+    /// Telegram keeps an album caption on one of its messages, usually the first one.
+    pub fn caption(&self) -> &entity::messages::Model {
+        self.messages
+            .iter()
+            .find(|message| !message.text.is_empty())
+            .unwrap_or_else(|| self.first())
+    }
+}
+
+/// Groups consecutive messages of the same album into one post, keeping the order otherwise.
+pub fn group_posts(messages: Vec<entity::messages::Model>) -> Vec<Post> {
+    let mut posts: Vec<Post> = vec![];
+
+    for message in messages {
+        if let Some(post) = posts.last_mut() {
+            let last = post.messages.last().unwrap();
+            if message.grouped_id.is_some()
+                && message.grouped_id == last.grouped_id
+                && message.chat_id == last.chat_id
+            {
+                post.messages.push(message);
+                continue;
+            }
+        }
+
+        posts.push(Post {
+            messages: vec![message],
+        });
+    }
+
+    posts
+}
@@ -0,0 +1,36 @@
+//! The content-addressed blob store media files end up in, laid out as
+//! `blobs/<first two hex digits>/<sha256><extension>` under the media path.
+
+use std::io;
+
+use sha2::{Digest, Sha256};
+use tokio::fs::{create_dir_all, File};
+use tokio::io::AsyncReadExt;
+
+pub async fn sha256_file(path: &str) -> io::Result<String> {
+    let mut file = File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Path of the blob with the given hash, creating its folder if needed.
+pub async fn create_blob_path(
+    media_path: &str,
+    sha256: &str,
+    extension: &str,
+) -> io::Result<String> {
+    let blob_dir = format!("{}/blobs/{}", media_path, &sha256[..2]);
+    create_dir_all(&blob_dir).await?;
+
+    Ok(format!("{}/{}{}", blob_dir, sha256, extension))
+}
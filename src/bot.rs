@@ -1,38 +1,86 @@
-
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
 use std::io;
-use std::io::{BufRead, Write};
+use std::io::{BufRead, SeekFrom, Write};
 use std::path::Path;
 use std::sync::Arc;
-use std::thread::sleep;
 use std::time::Duration;
 
-use grammers_client::types::Media::{Contact, Document, Photo, Sticker};
+use anyhow::bail;
+use grammers_client::types::Media::{Document, Photo, Sticker};
 use grammers_client::types::Message;
-use grammers_client::types::{Chat, Media};
+use grammers_client::types::{Chat, Downloadable, Media};
 use grammers_client::{Client, Config, InitParams, SignInError, Update};
-use grammers_session::Session;
+use grammers_session::{PackedChat, Session};
+use grammers_tl_types as tl;
 use log::{debug, error, info, warn};
 use mime::Mime;
 use moka::future::Cache;
-use tokio::fs::create_dir_all;
+use tokio::fs::{create_dir_all, remove_file, rename, OpenOptions};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
 use tokio::sync::{mpsc, Mutex, Semaphore};
+use tokio::task::JoinSet;
+use tokio::time::sleep;
 
-use crate::db::Db;
+use crate::blob::{create_blob_path, sha256_file};
+use crate::chat_kind::ChatKind;
+use crate::db::{Db, MediaState};
+use crate::rules::{MediaInfo, Rules};
+use crate::web_page::get_web_page_photo;
 
 pub type ApiId = i32;
 pub type ApiHash = String;
 
+/// Biggest chunk size Telegram allows, must stay the same for resumed downloads to line up
+const DOWNLOAD_CHUNK_SIZE: i32 = 512 * 1024;
+
+/// How many times a message is refetched for a fresh file reference before giving up
+const MAX_REFERENCE_REFRESHES: usize = 2;
+
+/// How many failed downloads a file gets before it is marked as failed for good, a pass of
+/// the media queue tries up to four times
+const MAX_DOWNLOAD_ATTEMPTS: i32 = 12;
+
+enum MediaDownload {
+    /// The media is in the blob store, either freshly downloaded or shared with other messages
+    Stored {
+        media: entity::media::Model,
+        media_type: String,
+    },
+    /// There is nothing to download, e.g. the photo was removed
+    Unavailable,
+    Skipped,
+    Expired,
+    /// The download failed but is retried on a later pass
+    Postponed,
+    /// The download failed too often and is given up
+    Failed,
+}
+
+pub struct ReconcileReport {
+    pub chat_id: i64,
+    pub chat_name: String,
+    pub server_messages: usize,
+    pub local_messages: usize,
+    pub marked_deleted: u64,
+    /// Whether the chat is still in the dialogs, i.e. whether it could be compared at all.
+    pub in_dialogs: bool,
+}
+
 #[derive(Clone)]
 pub struct Bot {
     client: Client,
     client_handler: Client,
     teledump_session_path: String,
     media_path: String,
+    rules: Arc<Rules>,
     db: Db,
     message_sender: mpsc::Sender<Message>,
     message_receiver: Arc<Mutex<mpsc::Receiver<Message>>>,
     download_semaphore: Arc<Semaphore>,
+    download_workers: usize,
+    /// Held while a file is downloaded, several messages of a batch may carry the same file
+    media_locks: Cache<i32, Arc<Mutex<()>>>,
 }
 
 impl Bot {
@@ -41,6 +89,8 @@ impl Bot {
         api_hash: ApiHash,
         teledump_session_path: String,
         media_path: String,
+        rules: Rules,
+        download_workers: usize,
         db: Db,
     ) -> anyhow::Result<Self> {
         let client = Client::connect(Config {
@@ -88,25 +138,32 @@ impl Bot {
 
         let (message_sender, message_receiver) = mpsc::channel(4096);
         let message_receiver = Arc::new(Mutex::new(message_receiver));
-        let download_semaphore = Arc::new(Semaphore::new(8));
+        let download_semaphore = Arc::new(Semaphore::new(download_workers));
+        let media_locks = Cache::builder()
+            .max_capacity(10_000)
+            .time_to_idle(Duration::from_secs(60 * 60))
+            .build();
 
         Ok(Bot {
             client,
             client_handler,
             teledump_session_path,
             media_path,
+            rules: Arc::new(rules),
             db,
             message_sender,
             message_receiver,
             download_semaphore,
+            download_workers,
+            media_locks,
         })
     }
 
     pub async fn run_event_loop(&self) -> anyhow::Result<()> {
         let message_process = tokio::spawn(self.clone().process_message_queue());
 
-        while let Err(_) = self.save_user_private_chats().await {
-            sleep(Duration::from_secs(10));
+        while let Err(_) = self.save_user_chats().await {
+            sleep(Duration::from_secs(10)).await;
         }
 
         let (updates_result, message_process_result, media_process_result) = tokio::join!(
@@ -124,65 +181,62 @@ impl Bot {
         }
     }
 
-    pub async fn get_user_private_chats(&self) -> anyhow::Result<Vec<Chat>> {
+    pub async fn get_user_chats(&self) -> anyhow::Result<Vec<Chat>> {
         let mut chats = vec![];
         let mut iter_dialogs = self.client_handler.iter_dialogs();
         while let Some(dialog) = iter_dialogs.next().await? {
-            let chat = dialog.chat;
-            if matches!(chat, Chat::User(_)) {
-                chats.push(chat);
-            }
+            self.db.save_chat(&dialog.chat).await?;
+            chats.push(dialog.chat);
         }
 
         Ok(chats)
     }
 
-    pub async fn save_user_private_chats(&self) -> anyhow::Result<()> {
-        let chats = self.get_user_private_chats().await?;
+    pub async fn save_user_chats(&self) -> anyhow::Result<()> {
+        let chats = self.get_user_chats().await?;
 
         for chat in &chats {
+            if !self.rules.is_chat_allowed(chat) {
+                info!("Chat {} is excluded by rules, skipping...", chat.id());
+                continue;
+            }
+
             let mut messages = self.client_handler.iter_messages(chat);
             let total_messages = messages.total().await?;
 
             println!(
-                "Chat {} has {} total messages.",
+                "Chat {} ({}) has {} total messages.",
                 chat.name(),
+                ChatKind::from(chat).as_str(),
                 total_messages
             );
 
+            // Already stored messages are walked as well, they may have been edited while offline
             let mut first_message = true;
-            let mut partial_sync = false;
-            let last_loaded_message_id_by_chat =
-                self.db.get_last_loaded_message_id_by_chat(chat.id()).await;
             while let Some(message) = messages.next().await? {
                 if first_message {
                     let last_message = self.db.get_last_message_by_chat(chat.id()).await;
                     if last_message.is_err() || message.date().to_string() != last_message?.date {
                         info!("Data for chat {} is outdated, full resync...", chat.id());
-                    } else if let Ok(last_message_id) = last_loaded_message_id_by_chat {
+                    } else {
                         info!(
-                            "Data for chat {} is mostly up to date, patrial sync...",
+                            "Data for chat {} is mostly up to date, checking stored messages for edits...",
                             chat.id()
                         );
-                        messages = messages.offset_id(last_message_id);
-                        partial_sync = true;
                     }
 
                     first_message = false;
                 }
                 let is_already_saved = self.save_message(&message).await.unwrap();
 
-                if is_already_saved {
-                    if let Ok(last_message_id) = last_loaded_message_id_by_chat {
-                        if !partial_sync {
-                            info!(
-                                "Data for chat {} is mostly up to date, patrial sync...",
-                                chat.id()
-                            );
-                            messages = messages.offset_id(last_message_id);
-                            partial_sync = true;
-                            // Or break it?
-                        }
+                if is_already_saved && self.db.is_message_edit_outdated(&message).await {
+                    if let Err(e) = self.handle_message_edit(&message).await {
+                        warn!(
+                            "Failed to save edit of message {} in chat {}: {}",
+                            message.id(),
+                            chat.id(),
+                            e
+                        );
                     }
                 }
             }
@@ -191,9 +245,103 @@ impl Bot {
         Ok(())
     }
 
+    /// Compares the archive against the server history and marks messages that only exist
+    /// locally as deleted while offline. Reconciles every archived chat if `chat_ids` is empty.
+    /// Archived chats that left the dialogs can't be compared and are reported as such.
+    pub async fn reconcile_chats(&self, chat_ids: &[i64]) -> anyhow::Result<Vec<ReconcileReport>> {
+        let mut reports = vec![];
+
+        let chats = self.get_user_chats().await?;
+        let dialog_ids: HashSet<i64> = chats.iter().map(|chat| chat.id()).collect();
+
+        for chat in chats {
+            if !chat_ids.is_empty() && !chat_ids.contains(&chat.id()) {
+                continue;
+            }
+
+            let local_ids = self.db.get_present_message_ids_by_chat(chat.id()).await?;
+            if local_ids.is_empty() {
+                continue;
+            }
+
+            info!("Reconciling chat {} with id {}...", chat.name(), chat.id());
+
+            let mut server_ids = HashSet::new();
+            let mut messages = self.client_handler.iter_messages(&chat);
+            while let Some(message) = messages.next().await? {
+                server_ids.insert(message.id());
+            }
+
+            let local_only_ids: Vec<i32> = local_ids
+                .iter()
+                .copied()
+                .filter(|id| !server_ids.contains(id))
+                .collect();
+
+            let marked_deleted = self
+                .db
+                .mark_messages_deleted_offline(chat.id(), &local_only_ids)
+                .await?;
+
+            reports.push(ReconcileReport {
+                chat_id: chat.id(),
+                chat_name: chat.name().to_string(),
+                server_messages: server_ids.len(),
+                local_messages: local_ids.len(),
+                marked_deleted,
+                in_dialogs: true,
+            });
+        }
+
+        // The chat was left, deleted or banned, its messages can't be told apart from deleted ones
+        let missing_ids: Vec<i64> = self
+            .db
+            .get_archived_chat_ids(chat_ids)
+            .await?
+            .into_iter()
+            .filter(|chat_id| !dialog_ids.contains(chat_id))
+            .collect();
+        let missing_chats = self.db.get_chats_by_ids(&missing_ids).await?;
+        for chat_id in missing_ids {
+            warn!(
+                "Chat {} is not in dialogs anymore, can't reconcile it",
+                chat_id
+            );
+
+            let chat_name = missing_chats
+                .iter()
+                .find(|chat| chat.id == chat_id)
+                .map(|chat| chat.title.clone())
+                .unwrap_or_default();
+            let local_messages = self
+                .db
+                .get_present_message_ids_by_chat(chat_id)
+                .await?
+                .len();
+
+            reports.push(ReconcileReport {
+                chat_id,
+                chat_name,
+                server_messages: 0,
+                local_messages,
+                marked_deleted: 0,
+                in_dialogs: false,
+            });
+        }
+
+        Ok(reports)
+    }
+
     async fn handle_updates(&self) -> anyhow::Result<()> {
         while let Some(update) = &self.client_handler.next_update().await? {
             match update {
+                Update::NewMessage(message) if !self.rules.is_chat_allowed(&message.chat()) => {
+                    debug!(
+                        "Chat {} is excluded by rules, ignoring message {}...",
+                        message.chat().id(),
+                        message.id()
+                    );
+                }
                 Update::NewMessage(message) if !message.outgoing() => {
                     match self.handle_message(&message).await {
                         Ok(_) => {}
@@ -206,6 +354,25 @@ impl Bot {
                         Err(_) => {}
                     }
                 }
+                Update::MessageDeleted(deletion) => {
+                    if let Err(e) = self
+                        .db
+                        .mark_messages_deleted(deletion.channel_id(), deletion.messages())
+                        .await
+                    {
+                        warn!("Failed to mark messages as deleted: {}", e);
+                    }
+                }
+                Update::MessageEdited(message) if self.rules.is_chat_allowed(&message.chat()) => {
+                    if let Err(e) = self.handle_message_edit(message).await {
+                        warn!(
+                            "Failed to save edit of message {} in chat {}: {}",
+                            message.id(),
+                            message.chat().id(),
+                            e
+                        );
+                    }
+                }
                 _ => {}
             };
         }
@@ -214,9 +381,8 @@ impl Bot {
     }
 
     async fn handle_message(&self, message: &Message) -> anyhow::Result<()> {
-        let mut save_message = false;
-
-        if message.sender().is_none() {
+        // Channel posts have no sender, everything else is expected to have one
+        if message.sender().is_none() && !matches!(message.chat(), Chat::Channel(_)) {
             warn!(
                 "Got a message but sender is none (chat name {}, id '{}'), not handling...",
                 message.chat().name(),
@@ -225,23 +391,46 @@ impl Bot {
             return Ok(());
         }
 
-        if matches!(message.chat(), Chat::User(_)) {
-            info!(
-                "Got a PM from user {} with id {}",
-                message.sender().unwrap().name(),
-                message.sender().unwrap().id()
-            );
-            save_message = true;
-        } else {
-            info!(
-                "Got a message from chat '{}' with id {} from userid {}",
-                message.chat().name(),
-                message.chat().id(),
-                message.sender().unwrap().id()
-            );
+        match message.chat() {
+            Chat::User(_) => {
+                info!(
+                    "Got a PM from user {} with id {}",
+                    message.sender().unwrap().name(),
+                    message.sender().unwrap().id()
+                );
+            }
+            Chat::Group(_) => {
+                info!(
+                    "Got a message from chat '{}' with id {} from userid {}",
+                    message.chat().name(),
+                    message.chat().id(),
+                    message.sender().unwrap().id()
+                );
+            }
+            Chat::Channel(_) => {
+                info!(
+                    "Got a post from channel '{}' with id {}",
+                    message.chat().name(),
+                    message.chat().id()
+                );
+            }
         }
 
-        if save_message {
+        self.save_message(message).await?;
+
+        Ok(())
+    }
+
+    async fn handle_message_edit(&self, message: &Message) -> anyhow::Result<()> {
+        let has_media = get_downloadable_media(message).is_some()
+            && self.rules.is_media_allowed(&message.chat());
+
+        if !self.db.save_message_edit(message, has_media).await? {
+            debug!(
+                "Edited message {} in chat {} is not archived yet, saving it...",
+                message.id(),
+                message.chat().id()
+            );
             self.save_message(message).await?;
         }
 
@@ -267,137 +456,584 @@ impl Bot {
         loop {
             while let Some(message) = receiver.recv().await {
                 let has_media = {
-                    match message.media() {
+                    match get_downloadable_media(&message) {
                         None => false,
-                        Some(_) => true,
+                        Some(_) => self.rules.is_media_allowed(&message.chat()),
                     }
                 };
 
-                self.db.save_message(&message, has_media).await.unwrap();
+                if let Chat::Channel(_) = message.chat() {
+                    self.db
+                        .save_channel_post(&message, has_media)
+                        .await
+                        .unwrap();
+                } else {
+                    self.db.save_message(&message, has_media).await.unwrap();
+                }
             }
         }
     }
 
     pub async fn process_media_queue(&self) -> anyhow::Result<()> {
-        let dialog_cache = Cache::builder()
-            .max_capacity(1_000)
-            .time_to_live(Duration::from_secs(60 * 10))
-            .build();
+        // A few downloads per worker, so that workers don't idle while the batch drains
+        let batch_size = self.download_workers * 4;
 
         loop {
-            sleep(Duration::from_secs(15));
-
-            while let Some(message_model) = self.db.get_message_with_media_not_downloaded().await {
-                let chat = {
-                    if !dialog_cache.contains_key(&message_model.chat_id) {
-                        let chats = self.get_user_private_chats().await?;
-                        for chat in chats {
-                            dialog_cache.insert(chat.id(), chat).await;
-                        }
-                    }
+            sleep(Duration::from_secs(15)).await;
 
-                    dialog_cache.get(&message_model.chat_id).await.unwrap()
-                };
-                let messages = self
-                    .client_handler
-                    .get_messages_by_id(chat, &vec![message_model.id])
+            // Rebuilt every pass, so that chats joined in the meantime are picked up
+            let mut dialogs: HashMap<i64, Chat> = HashMap::new();
+            let mut dialogs_loaded = false;
+
+            loop {
+                let batch = self
+                    .db
+                    .get_messages_with_media_not_downloaded(batch_size)
                     .await?;
+                if batch.is_empty() {
+                    break;
+                }
 
-                if let Some(message) = messages.first().unwrap() {
-                    let mut media_type = None;
-                    let mut media_path = None;
-                    if let Some(media) = message.media() {
-                        let mut restart = false;
-                        let mut expired = false;
-                        match media {
-                            Photo(_) | Document(_) | Sticker(_) | Contact(_) => {
-                                media_type = Some(get_file_extension(&media));
-                                let mut media_name = media_type.clone().unwrap();
-                                if let Document(document) = &media {
-                                    info!(
-                                        "Downloading document from message {} with size {} kbytes...",
-                                        message.id(),
-                                        document.size() / 1024
-                                    );
-                                    media_name = if document.name().is_empty() {
-                                        format!(
-                                            "-unknown{}",
-                                            media_type.clone().unwrap_or(".unknown".to_string())
-                                        )
-                                    } else {
-                                        format!("-{}", document.name())
-                                    };
-                                }
-
-                                let dst =
-                                    format!("{}/chat-{}", self.media_path, message.chat().id());
-                                create_dir_all(&dst).await?;
-
-                                media_path = Some(format!(
-                                    "{}/media-{}{}",
-                                    dst,
-                                    message.id().to_string(),
-                                    media_name
-                                ));
-
-                                if let Photo(photo) = media {
-                                    if photo.raw.photo.is_some() {
-                                        let mut attempt = 0;
-                                        while let Err(e) = message
-                                            .download_media(media_path.clone().unwrap())
-                                            .await
-                                        {
-                                            if e.to_string().contains("FILE_REFERENCE_EXPIRED") {
-                                                warn!("File reference expired for media in message id {}", message.id());
-                                                expired = true;
-                                                break;
-                                            }
-                                            if attempt >= 3 {
-                                                warn!("Failed to download media from message {}, restarting...", message.id());
-                                                restart = true;
-                                                break;
-                                            }
-                                            attempt += 1;
-                                            warn!("Failed to download media from message {}, retrying after 5 secs, attempt {}... {}", message.id(), attempt, e);
-                                            sleep(Duration::from_secs(5));
-                                        }
-
-                                        if expired || restart {
-                                            info!(
-                                                "Download media from message {} done!",
-                                                message.id()
-                                            );
-                                        }
-                                    } else {
-                                        warn!(
-                                            "Bypassing media download from message {}...",
-                                            message.id()
-                                        );
-                                        media_path = None;
-                                        media_type = None;
-                                    }
-                                }
+                let mut stalled = false;
+                let mut downloads = JoinSet::new();
+                for message_model in batch {
+                    // Left chats would otherwise walk all dialogs for every one of their messages
+                    if !dialogs.contains_key(&message_model.chat_id) && !dialogs_loaded {
+                        dialogs_loaded = true;
+                        match self.get_user_chats().await {
+                            Ok(chats) => {
+                                dialogs = chats.into_iter().map(|chat| (chat.id(), chat)).collect();
+                            }
+                            Err(e) => {
+                                warn!("Failed to refresh dialogs, postponing media: {}", e);
+                                stalled = true;
+                                break;
                             }
-                            _ => {}
-                        };
-
-                        if !restart {
-                            self.db
-                                .save_message_media_status(
-                                    message_model,
-                                    true,
-                                    media_path,
-                                    media_type,
-                                    expired,
-                                )
-                                .await?;
                         }
                     }
+
+                    // The chat may come back, e.g. after rejoining, so its media stays pending
+                    let Some(chat) = dialogs.get(&message_model.chat_id).cloned() else {
+                        warn!(
+                            "Chat {} is not in dialogs, postponing media of message {}",
+                            message_model.chat_id, message_model.id
+                        );
+                        self.db.save_message_media_attempt(&message_model).await?;
+                        stalled = true;
+                        continue;
+                    };
+
+                    self.db.save_message_media_attempt(&message_model).await?;
+
+                    let permit = self.download_semaphore.clone().acquire_owned().await?;
+                    let bot = self.clone();
+                    downloads.spawn(async move {
+                        let result = bot.process_media_message(message_model, chat).await;
+                        drop(permit);
+                        result
+                    });
+                }
+
+                while let Some(result) = downloads.join_next().await {
+                    match result {
+                        Ok(Ok(true)) => {}
+                        Ok(Ok(false)) => stalled = true,
+                        Ok(Err(e)) => {
+                            error!("Failed to process media: {}", e);
+                            stalled = true;
+                        }
+                        Err(e) => {
+                            error!("Media download task failed: {}", e);
+                            stalled = true;
+                        }
+                    }
+                }
+
+                // Give the connection some rest, failed messages are retried on the next pass
+                if stalled {
+                    break;
                 }
             }
+
+            self.process_profile_photos().await?;
         }
     }
 
+    /// Downloads media of a single message, returns `false` if it should be retried later.
+    async fn process_media_message(
+        &self,
+        message_model: entity::messages::Model,
+        chat: Chat,
+    ) -> anyhow::Result<bool> {
+        let messages = self
+            .client_handler
+            .get_messages_by_id(chat, &vec![message_model.id])
+            .await?;
+
+        let message = messages.into_iter().next().flatten();
+        let Some((message, media)) = message
+            .and_then(|message| get_downloadable_media(&message).map(|media| (message, media)))
+        else {
+            warn!(
+                "Message {} in chat {} has no media anymore, skipping...",
+                message_model.id, message_model.chat_id
+            );
+            self.db
+                .save_message_media_status(message_model, true, None, None, false)
+                .await?;
+            return Ok(true);
+        };
+
+        let Some(media_kind) = get_media_kind(&media) else {
+            warn!("Bypassing media download from message {}...", message.id());
+            self.db
+                .save_message_media_status(message_model, true, None, None, false)
+                .await?;
+            return Ok(true);
+        };
+
+        let stored = self
+            .db
+            .save_media(&media, media_kind, message_model.media_id)
+            .await?;
+        let message_model = self.db.attach_message_media(message_model, &stored).await?;
+
+        // Another message with the same file may be downloading it right now, its result is
+        // picked up once it is done
+        let lock = self
+            .media_locks
+            .get_with(stored.id, async { Arc::new(Mutex::new(())) })
+            .await;
+        let _guard = lock.lock().await;
+        let stored = self.db.get_media(stored.id).await?.unwrap_or(stored);
+
+        match self
+            .download_message_media(&message, &media, stored)
+            .await?
+        {
+            MediaDownload::Stored { media, media_type } => {
+                self.db
+                    .save_message_media_status(
+                        message_model,
+                        true,
+                        media.path,
+                        Some(media_type),
+                        false,
+                    )
+                    .await?;
+            }
+            MediaDownload::Unavailable => {
+                self.db
+                    .save_message_media_status(message_model, true, None, None, false)
+                    .await?;
+            }
+            MediaDownload::Skipped => {
+                self.db.save_message_media_skipped(message_model).await?;
+            }
+            MediaDownload::Expired => {
+                self.db
+                    .save_message_media_status(message_model, true, None, None, true)
+                    .await?;
+            }
+            MediaDownload::Failed => {
+                self.db
+                    .save_message_media_status(message_model, true, None, None, false)
+                    .await?;
+            }
+            MediaDownload::Postponed => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    async fn download_message_media(
+        &self,
+        message: &Message,
+        media: &Media,
+        stored: entity::media::Model,
+    ) -> anyhow::Result<MediaDownload> {
+        let media_type = get_file_extension(media);
+        let media_kind = stored.kind.clone().unwrap_or_default();
+
+        // Identical files are shared by all messages, no matter how often they were forwarded
+        if stored.state == MediaState::Downloaded.as_str() && stored.path.is_some() {
+            debug!(
+                "Media {} of message {} is already stored, linking it...",
+                stored.id,
+                message.id()
+            );
+            return Ok(MediaDownload::Stored {
+                media: stored,
+                media_type,
+            });
+        }
+
+        if stored.state == MediaState::Failed.as_str() && stored.attempts >= MAX_DOWNLOAD_ATTEMPTS {
+            debug!(
+                "Media {} of message {} failed for good, skipping...",
+                stored.id,
+                message.id()
+            );
+            return Ok(MediaDownload::Failed);
+        }
+
+        let media_info = MediaInfo {
+            kind: &media_kind,
+            mime_type: stored.mime_type.as_deref(),
+            size: stored.size,
+        };
+        if !self.rules.is_download_allowed(&message.chat(), &media_info) {
+            info!(
+                "Skipping {} from message {} by download policy",
+                media_kind,
+                message.id()
+            );
+            self.db
+                .save_media_state(&stored, MediaState::Skipped)
+                .await?;
+            return Ok(MediaDownload::Skipped);
+        }
+
+        info!(
+            "Downloading {} from message {}{}...",
+            media_kind,
+            message.id(),
+            stored
+                .size
+                .map(|size| format!(" with size {} kbytes", size / 1024))
+                .unwrap_or_default()
+        );
+
+        let downloads_dir = format!("{}/blobs/downloads", self.media_path);
+        create_dir_all(&downloads_dir).await?;
+
+        let media_path = format!("{}/media-{}{}", downloads_dir, stored.id, media_type);
+
+        // Photos report the size of their largest thumbnail, which is not exact
+        let expected_size = match media {
+            Photo(_) => None,
+            _ => stored.size,
+        };
+        let mut message = message.clone();
+        let mut media = media.clone();
+        let mut attempt = 0;
+        let mut attempts = stored.attempts;
+        let mut reference_refreshes = 0;
+        while let Err(e) = self
+            .download_resumable(&media, &media_path, expected_size)
+            .await
+        {
+            self.db.save_media_failure(&stored, &e.to_string()).await?;
+            attempts += 1;
+
+            if e.to_string().contains("FILE_REFERENCE_EXPIRED") {
+                if reference_refreshes >= MAX_REFERENCE_REFRESHES {
+                    warn!(
+                        "File reference expired for media in message id {}",
+                        message.id()
+                    );
+                    self.db
+                        .save_media_state(&stored, MediaState::Expired)
+                        .await?;
+                    return Ok(MediaDownload::Expired);
+                }
+                reference_refreshes += 1;
+
+                // Refetching the message hands out a fresh file reference
+                info!(
+                    "File reference expired for media in message id {}, refetching message...",
+                    message.id()
+                );
+                let refetched = self.refetch_message(&message).await?;
+                match refetched.and_then(|message| {
+                    get_downloadable_media(&message).map(|media| (message, media))
+                }) {
+                    Some((refetched, refetched_media)) => {
+                        message = refetched;
+                        media = refetched_media;
+                    }
+                    None => {
+                        warn!("Message {} has no media anymore, skipping...", message.id());
+                        self.db
+                            .save_media_state(&stored, MediaState::Unavailable)
+                            .await?;
+                        return Ok(MediaDownload::Unavailable);
+                    }
+                }
+                continue;
+            }
+            if attempts >= MAX_DOWNLOAD_ATTEMPTS {
+                warn!(
+                    "Failed to download media from message {} {} times, giving up... {}",
+                    message.id(),
+                    attempts,
+                    e
+                );
+                self.db
+                    .save_media_state(&stored, MediaState::Failed)
+                    .await?;
+                return Ok(MediaDownload::Failed);
+            }
+            if attempt >= 3 {
+                warn!(
+                    "Failed to download media from message {}, restarting...",
+                    message.id()
+                );
+                return Ok(MediaDownload::Postponed);
+            }
+            attempt += 1;
+            warn!(
+                "Failed to download media from message {}, retrying after 5 secs, attempt {}... {}",
+                message.id(),
+                attempt,
+                e
+            );
+            sleep(Duration::from_secs(5)).await;
+        }
+
+        info!("Downloaded {} from message {}", media_kind, message.id());
+
+        let stored = self.store_blob(stored, &media_path, &media_type).await?;
+
+        Ok(MediaDownload::Stored {
+            media: stored,
+            media_type,
+        })
+    }
+
+    /// Moves a downloaded file into the blob store, dropping it if already stored.
+    async fn store_blob(
+        &self,
+        stored: entity::media::Model,
+        path: &str,
+        media_type: &str,
+    ) -> anyhow::Result<entity::media::Model> {
+        let sha256 = sha256_file(path).await?;
+        let size = tokio::fs::metadata(path).await?.len() as i64;
+
+        let blob_path = match self.db.get_downloaded_media_by_sha256(&sha256).await? {
+            Some(duplicate) => {
+                let blob_path = duplicate.path.unwrap_or_default();
+                debug!("{} is a duplicate of {}, dropping it...", path, blob_path);
+                remove_file(path).await?;
+                blob_path
+            }
+            None => {
+                let blob_path = create_blob_path(&self.media_path, &sha256, media_type).await?;
+                rename(path, &blob_path).await?;
+                blob_path
+            }
+        };
+
+        self.db
+            .save_media_downloaded(stored, sha256, blob_path, size)
+            .await
+    }
+
+    /// Downloads media into `<path>.part` in fixed size chunks and renames it into place once
+    /// complete. The part file only ever keeps whole chunks, so its length is the offset an
+    /// interrupted download resumes from.
+    async fn download_resumable(
+        &self,
+        media: &Media,
+        path: &str,
+        expected_size: Option<i64>,
+    ) -> anyhow::Result<()> {
+        let part_path = format!("{}.part", path);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(&part_path)
+            .await?;
+
+        let chunk_size = DOWNLOAD_CHUNK_SIZE as u64;
+        let offset = file.metadata().await?.len() / chunk_size * chunk_size;
+        file.set_len(offset).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+
+        if offset > 0 {
+            info!(
+                "Resuming download of {} from {} kbytes...",
+                path,
+                offset / 1024
+            );
+        }
+
+        let mut download = self
+            .client_handler
+            .iter_download(&Downloadable::Media(media.clone()))
+            .chunk_size(DOWNLOAD_CHUNK_SIZE)
+            .skip_chunks((offset / chunk_size) as i32);
+        while let Some(chunk) = download.next().await? {
+            file.write_all(&chunk).await?;
+            file.flush().await?;
+        }
+        file.sync_all().await?;
+
+        let size = file.metadata().await?.len();
+        drop(file);
+
+        if let Some(expected_size) = expected_size {
+            if size != expected_size as u64 {
+                remove_file(&part_path).await?;
+                bail!(
+                    "Downloaded {} bytes of {} but expected {}",
+                    size,
+                    path,
+                    expected_size
+                );
+            }
+        }
+
+        rename(&part_path, path).await?;
+
+        Ok(())
+    }
+
+    async fn refetch_message(&self, message: &Message) -> anyhow::Result<Option<Message>> {
+        let messages = self
+            .client_handler
+            .get_messages_by_id(message.chat(), &[message.id()])
+            .await?;
+
+        Ok(messages.into_iter().next().flatten())
+    }
+
+    /// Retries media whose file reference expired, returns how many of them were recovered.
+    pub async fn retry_expired_media(&self, chat_ids: &[i64]) -> anyhow::Result<usize> {
+        let expired = self.db.get_messages_with_expired_media(chat_ids).await?;
+        if expired.is_empty() {
+            return Ok(0);
+        }
+
+        let chats: HashMap<i64, Chat> = self
+            .get_user_chats()
+            .await?
+            .into_iter()
+            .map(|chat| (chat.id(), chat))
+            .collect();
+
+        let total = expired.len();
+        for message_model in expired {
+            let Some(chat) = chats.get(&message_model.chat_id) else {
+                warn!(
+                    "Chat {} is not in dialogs anymore, skipping media of message {}...",
+                    message_model.chat_id, message_model.id
+                );
+                continue;
+            };
+
+            let (chat_id, message_id) = (message_model.chat_id, message_model.id);
+            if let Err(e) = self
+                .process_media_message(message_model, chat.clone())
+                .await
+            {
+                warn!(
+                    "Failed to retry media of message {} in chat {}: {}",
+                    message_id, chat_id, e
+                );
+            }
+        }
+
+        let still_expired = self.db.get_messages_with_expired_media(chat_ids).await?;
+
+        Ok(total - still_expired.len())
+    }
+
+    async fn process_profile_photos(&self) -> anyhow::Result<()> {
+        while let Some((revision, user)) = self.db.get_profile_photo_not_downloaded().await? {
+            let packed = user
+                .packed
+                .as_deref()
+                .and_then(|packed| PackedChat::from_bytes(packed).ok());
+
+            // Replaced photos stay in the profile history unless the user deleted them
+            let photo = match (packed, revision.photo_id) {
+                (Some(packed), Some(photo_id)) => self.find_profile_photo(packed, photo_id).await,
+                _ => Ok(None),
+            };
+            let photo = match photo {
+                Ok(photo) => photo,
+                Err(e) => {
+                    warn!("Failed to fetch profile photos of user {}: {}", user.id, e);
+                    break;
+                }
+            };
+
+            let Some(photo) = photo else {
+                warn!(
+                    "Profile photo version {} of user {} is no longer available, skipping...",
+                    revision.version, user.id
+                );
+                self.db.save_profile_photo_status(revision, None).await?;
+                continue;
+            };
+
+            // Profile photos share the blob store with message media, so failed downloads are
+            // counted the same way
+            let media = Photo(photo);
+            let stored = self.db.save_media(&media, "photo", None).await?;
+            if stored.state == MediaState::Downloaded.as_str() && stored.path.is_some() {
+                self.db
+                    .save_profile_photo_status(revision, Some(&stored))
+                    .await?;
+                continue;
+            }
+
+            let downloads_dir = format!("{}/blobs/downloads", self.media_path);
+            create_dir_all(&downloads_dir).await?;
+
+            let photo_path = format!("{}/media-{}.jpg", downloads_dir, stored.id);
+            match self.download_resumable(&media, &photo_path, None).await {
+                Ok(_) => {
+                    let stored = self.store_blob(stored, &photo_path, ".jpg").await?;
+                    info!("Downloaded profile photo of user {}", user.id);
+                    self.db
+                        .save_profile_photo_status(revision, Some(&stored))
+                        .await?;
+                }
+                Err(e) => {
+                    self.db.save_media_failure(&stored, &e.to_string()).await?;
+
+                    if stored.attempts + 1 < MAX_DOWNLOAD_ATTEMPTS {
+                        warn!(
+                            "Failed to download profile photo of user {}, retrying later... {}",
+                            user.id, e
+                        );
+                        break;
+                    }
+
+                    warn!(
+                        "Failed to download profile photo of user {} {} times, giving up... {}",
+                        user.id, MAX_DOWNLOAD_ATTEMPTS, e
+                    );
+                    self.db
+                        .save_media_state(&stored, MediaState::Failed)
+                        .await?;
+                    self.db.save_profile_photo_status(revision, None).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Looks up a photo in the profile photo history of a user.
+    async fn find_profile_photo(
+        &self,
+        user: PackedChat,
+        photo_id: i64,
+    ) -> anyhow::Result<Option<grammers_client::types::Photo>> {
+        let mut photos = self.client_handler.iter_profile_photos(user);
+        while let Some(photo) = photos.next().await? {
+            if photo.id() == photo_id {
+                return Ok(Some(photo));
+            }
+        }
+
+        Ok(None)
+    }
+
     pub fn save_session(&self) -> anyhow::Result<()> {
         let _ = &self
             .client
@@ -428,29 +1064,70 @@ fn get_file_extension(media: &Media) -> String {
         Sticker(sticker) => {
             get_mime_extension(sticker.document.mime_type()).unwrap_or(".sticker".to_string())
         }
-        Document(document) => {
-            let mime_type = document.mime_type();
-
-            return if mime_type.is_none() || mime_type.unwrap().is_empty() {
-                format!(
-                    ".{}",
-                    Path::new(document.name())
-                        .extension()
-                        .and_then(OsStr::to_str)
-                        .unwrap_or_default()
-                )
-            } else {
-                get_mime_extension(document.mime_type()).unwrap()
-            };
-        }
-        Contact(_) => ".vcf".to_string(),
+        Document(document) => get_mime_extension(document.mime_type())
+            .or_else(|| get_name_extension(document.name()))
+            .unwrap_or(".bin".to_string()),
         _ => String::new(),
     }
 }
 
+/// Extension for a MIME type, `None` if the type is missing or can't be parsed.
 fn get_mime_extension(mime_type: Option<&str>) -> Option<String> {
-    return mime_type.map(|m| {
-        let mime: Mime = m.parse().unwrap();
-        format!(".{}", mime.subtype().to_string())
-    });
+    let mime: Mime = mime_type?.parse().ok()?;
+    Some(format!(".{}", mime.subtype()))
+}
+
+fn get_name_extension(name: &str) -> Option<String> {
+    Path::new(name)
+        .extension()
+        .and_then(OsStr::to_str)
+        .filter(|extension| !extension.is_empty())
+        .map(|extension| format!(".{}", extension))
+}
+
+/// Media with a file behind it, including the photo of a link preview. Everything else is
+/// stored as structured data.
+fn get_downloadable_media(message: &Message) -> Option<Media> {
+    match message.media() {
+        Some(media @ (Photo(_) | Document(_) | Sticker(_))) => Some(media),
+        _ => get_web_page_photo(message),
+    }
+}
+
+/// Kind of downloadable media, `None` if there is nothing to download.
+fn get_media_kind(media: &Media) -> Option<&'static str> {
+    match media {
+        Photo(photo) if photo.raw.photo.is_none() => None,
+        Photo(_) => Some("photo"),
+        Sticker(_) => Some("sticker"),
+        Document(document) => Some(get_document_kind(document)),
+        _ => None,
+    }
+}
+
+fn get_document_kind(document: &grammers_client::types::Document) -> &'static str {
+    use tl::enums::DocumentAttribute as A;
+
+    let attributes = match &document.raw.document {
+        Some(tl::enums::Document::Document(document)) => document.attributes.as_slice(),
+        _ => &[],
+    };
+
+    if attributes.iter().any(|a| matches!(a, A::Sticker(_))) {
+        return "sticker";
+    }
+    if attributes.iter().any(|a| matches!(a, A::Animated)) {
+        return "animation";
+    }
+    for attribute in attributes {
+        match attribute {
+            A::Video(video) if video.round_message => return "round_video",
+            A::Video(_) => return "video",
+            A::Audio(audio) if audio.voice => return "voice",
+            A::Audio(_) => return "audio",
+            _ => {}
+        }
+    }
+
+    "document"
 }
@@ -0,0 +1,50 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use grammers_client::types::Chat;
+use serde::Deserialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatKind {
+    User,
+    Group,
+    Supergroup,
+    Channel,
+}
+
+impl ChatKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChatKind::User => "user",
+            ChatKind::Group => "group",
+            ChatKind::Supergroup => "supergroup",
+            ChatKind::Channel => "channel",
+        }
+    }
+}
+
+impl FromStr for ChatKind {
+    type Err = anyhow::Error;
+
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        match kind {
+            "user" => Ok(ChatKind::User),
+            "group" => Ok(ChatKind::Group),
+            "supergroup" => Ok(ChatKind::Supergroup),
+            "channel" => Ok(ChatKind::Channel),
+            _ => Err(anyhow!("Unknown chat kind {}", kind)),
+        }
+    }
+}
+
+impl From<&Chat> for ChatKind {
+    fn from(chat: &Chat) -> Self {
+        match chat {
+            Chat::User(_) => ChatKind::User,
+            Chat::Group(group) if group.is_megagroup() => ChatKind::Supergroup,
+            Chat::Group(_) => ChatKind::Group,
+            Chat::Channel(_) => ChatKind::Channel,
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::path::PathBuf;
+
+use chrono::{DateTime, Days, FixedOffset, NaiveDate, NaiveTime, TimeZone, Utc};
+use clap::{Parser, Subcommand};
+
+use crate::export::ExportFormat;
+use crate::formatting::Format;
+
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Archive chats and follow new messages (default)
+    Run,
+    /// List archived messages that were deleted in a chat
+    Deleted {
+        /// Chat id to list deleted messages for
+        chat_id: i64,
+    },
+    /// Compare the archive with the server and mark messages deleted while offline
+    Reconcile {
+        /// Chat ids to reconcile, all archived chats if omitted
+        chat_ids: Vec<i64>,
+    },
+    /// Queue media skipped by download policies again, e.g. after lifting a policy
+    BackfillSkipped {
+        /// Chat ids to backfill, all chats if omitted
+        chat_ids: Vec<i64>,
+    },
+    /// Retry downloading media whose file reference expired
+    RetryExpired {
+        /// Chat ids to retry, all chats if omitted
+        chat_ids: Vec<i64>,
+    },
+    /// Show a message together with its edit history
+    History {
+        /// Chat id the message belongs to
+        chat_id: i64,
+        /// Message id
+        message_id: i32,
+        /// How to render message formatting
+        #[arg(long, value_enum, default_value_t = Format::Plain)]
+        format: Format,
+    },
+    /// Export archived chats for other tools to read
+    Export {
+        /// Chat ids to export, all archived chats if omitted
+        chat_ids: Vec<i64>,
+        /// Directory to write the export into
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Format of the export
+        #[arg(long, value_enum, default_value_t = ExportFormat::Json)]
+        format: ExportFormat,
+        /// Only export messages sent on or after this date (YYYY-MM-DD or RFC 3339)
+        #[arg(long, value_parser = parse_since)]
+        since: Option<DateTime<Utc>>,
+        /// Only export messages sent before the end of this date (YYYY-MM-DD or RFC 3339)
+        #[arg(long, value_parser = parse_until)]
+        until: Option<DateTime<Utc>>,
+        /// Only export messages from this sender id, can be repeated
+        #[arg(long = "from")]
+        senders: Vec<i64>,
+    },
+    /// Import a Telegram Desktop export into the archive
+    Import {
+        /// `result.json` of the export, or the folder containing it
+        path: PathBuf,
+        /// UTC offset of the computer that made the export, e.g. `+02:00`. Only needed for old
+        /// exports without Unix timestamps, whose dates are in its local time
+        #[arg(long, value_parser = parse_utc_offset)]
+        utc_offset: Option<FixedOffset>,
+    },
+    /// Search archived messages by text
+    Search {
+        /// Words, "exact phrases", prefixes like `arch*`, combined with AND, OR and NOT
+        query: String,
+        /// Only search in this chat id, can be repeated
+        #[arg(long = "chat")]
+        chat_ids: Vec<i64>,
+        /// Only search messages from this sender id, can be repeated
+        #[arg(long = "from")]
+        senders: Vec<i64>,
+        /// Only search messages sent on or after this date (YYYY-MM-DD or RFC 3339)
+        #[arg(long, value_parser = parse_since)]
+        since: Option<DateTime<Utc>>,
+        /// Only search messages sent before the end of this date (YYYY-MM-DD or RFC 3339)
+        #[arg(long, value_parser = parse_until)]
+        until: Option<DateTime<Utc>>,
+        /// Maximum number of results
+        #[arg(long, default_value_t = 50)]
+        limit: u64,
+    },
+}
+
+fn parse_since(value: &str) -> Result<DateTime<Utc>, String> {
+    parse_date_arg(value, false)
+}
+
+fn parse_until(value: &str) -> Result<DateTime<Utc>, String> {
+    parse_date_arg(value, true)
+}
+
+/// Parses a date given on the command line. A bare day covers the whole day, so as an upper
+/// bound it means the start of the next day.
+fn parse_date_arg(value: &str, end_of_day: bool) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Ok(date.with_timezone(&Utc));
+    }
+
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| format!("invalid date `{}`, expected YYYY-MM-DD or RFC 3339", value))?;
+    let date = if end_of_day {
+        date.checked_add_days(Days::new(1))
+            .ok_or("date out of range")?
+    } else {
+        date
+    };
+
+    Ok(Utc.from_utc_datetime(&date.and_time(NaiveTime::MIN)))
+}
+
+fn parse_utc_offset(value: &str) -> Result<FixedOffset, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid UTC offset `{}`, expected e.g. +02:00", value))
+}
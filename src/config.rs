@@ -1,10 +1,14 @@
 use crate::bot::{ApiHash, ApiId};
+use crate::rules::Rules;
 use std::env;
 use std::fs::create_dir_all;
+use std::path::Path;
 
 static API_ID: &str = "API_ID";
 static API_HASH: &str = "API_HASH";
 static STORE_PATH: &str = "STORE_PATH";
+static RULES_PATH: &str = "RULES_PATH";
+static DOWNLOAD_WORKERS: &str = "DOWNLOAD_WORKERS";
 
 pub struct Config {
     pub api_id: ApiId,
@@ -13,6 +17,8 @@ pub struct Config {
     pub media_path: String,
     pub database_url: String,
     pub teledump_session_path: String,
+    pub rules: Rules,
+    pub download_workers: usize,
 }
 
 impl Config {
@@ -37,13 +43,33 @@ impl Config {
 
         let teledump_session_path = format!("{}/teledump.session", store_path);
 
+        let rules = {
+            let rules_path = env::var(RULES_PATH)
+                .map(|rules_path| shellexpand::full(&rules_path).ok().unwrap().to_string())
+                .unwrap_or(format!("{}/rules.json", store_path));
+
+            Rules::load(Path::new(&rules_path))
+                .expect(&format!("Failed to load rules from {rules_path}"))
+        };
+
+        let download_workers = env::var(DOWNLOAD_WORKERS)
+            .map(|download_workers| {
+                download_workers
+                    .parse::<usize>()
+                    .expect(&format!("Failed to parse {DOWNLOAD_WORKERS}"))
+            })
+            .unwrap_or(8)
+            .max(1);
+
         Config {
             api_id,
             api_hash,
             store_path,
             media_path,
             database_url,
-            teledump_session_path
+            teledump_session_path,
+            rules,
+            download_workers,
         }
     }
 }
@@ -1,18 +1,74 @@
+use crate::chat_kind::ChatKind;
+use crate::formatting;
+use crate::media_data::media_data_to_json;
+use crate::web_page::web_page_to_json;
 use anyhow::anyhow;
-use grammers_client::types::Message;
-use log::info;
+use chrono::{DateTime, TimeZone, Utc};
+use grammers_client::types::{Chat, Document, Media, Message, User};
+use grammers_tl_types as tl;
+use log::{debug, info};
 use migration::{Migrator, MigratorTrait};
 use moka::future::Cache;
+use sea_orm::sea_query::Expr;
 use sea_orm::{
-    sea_query, ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection, EntityTrait,
-    PaginatorTrait, QueryFilter, QueryOrder,
+    sea_query, ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection, DbBackend,
+    EntityTrait, FromQueryResult, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Statement,
+    Value,
 };
 use std::time::Duration;
 
+/// Download state of a media row.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MediaState {
+    Pending,
+    Downloaded,
+    Skipped,
+    Expired,
+    Failed,
+    Unavailable,
+}
+
+impl MediaState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MediaState::Pending => "pending",
+            MediaState::Downloaded => "downloaded",
+            MediaState::Skipped => "skipped",
+            MediaState::Expired => "expired",
+            MediaState::Failed => "failed",
+            MediaState::Unavailable => "unavailable",
+        }
+    }
+}
+
+/// A full-text search over message texts, see the FTS5 docs for the query syntax.
+pub struct MessageSearch<'a> {
+    pub query: &'a str,
+    pub chat_ids: &'a [i64],
+    pub senders: &'a [i64],
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: u64,
+    /// Markers put around matched terms in the snippet
+    pub highlight: (&'a str, &'a str),
+}
+
+#[derive(Debug, FromQueryResult)]
+pub struct SearchResult {
+    pub chat_id: i64,
+    pub id: i32,
+    pub user_id: i64,
+    pub date: String,
+    pub deleted_at: Option<String>,
+    pub snippet: String,
+}
+
 #[derive(Clone)]
 pub struct Db {
     db: DatabaseConnection,
     message_cache: Cache<i64, Vec<entity::messages::Model>>,
+    user_cache: Cache<i64, entity::users::Model>,
+    chat_cache: Cache<i64, entity::chats::Model>,
 }
 
 impl Db {
@@ -26,10 +82,20 @@ impl Db {
             .max_capacity(10_000)
             .time_to_live(Duration::from_secs(60 * 10))
             .build();
+        let user_cache = Cache::builder()
+            .max_capacity(10_000)
+            .time_to_live(Duration::from_secs(60 * 10))
+            .build();
+        let chat_cache = Cache::builder()
+            .max_capacity(10_000)
+            .time_to_live(Duration::from_secs(60 * 10))
+            .build();
 
         Db {
             db: connection,
             message_cache,
+            user_cache,
+            chat_cache,
         }
     }
 
@@ -47,6 +113,23 @@ impl Db {
         return if let Some(_) = msg { true } else { false };
     }
 
+    /// Checks whether the archived copy of a message predates its latest edit, e.g. because it
+    /// was edited while the bot was offline.
+    pub async fn is_message_edit_outdated(&self, message: &Message) -> bool {
+        let Some(edit_date) = message.edit_date().map(|date| date.to_string()) else {
+            return false;
+        };
+
+        let Some(messages) = self.message_cache.get(&message.chat().id()).await else {
+            return false;
+        };
+
+        messages
+            .iter()
+            .find(|msg| msg.id == message.id())
+            .is_some_and(|msg| msg.edit_date.as_ref() != Some(&edit_date))
+    }
+
     async fn preload_messages_by_chat_id(&self, chat_id: i64) {
         let messages = entity::prelude::Messages::find()
             .filter(entity::messages::Column::ChatId.eq(chat_id))
@@ -65,20 +148,47 @@ impl Db {
     }
 
     pub async fn save_message(&self, message: &Message, has_media: bool) -> anyhow::Result<()> {
-        let message_model = entity::messages::ActiveModel {
-            id: ActiveValue::set(message.id()),
-            chat_id: ActiveValue::Set(message.chat().id()),
-            user_id: ActiveValue::Set(message.sender().unwrap().id()),
-            text: ActiveValue::Set(message.text().to_string()),
-            has_binary_data: ActiveValue::Set(has_media),
-            binary_data_downloaded: ActiveValue::Set(false),
-            binary_data_path: ActiveValue::Set(None),
-            binary_data_type: ActiveValue::Set(None),
-            binary_data_reference_expired: ActiveValue::Set(false),
-            date: ActiveValue::Set(message.date().to_string()),
-        };
+        let mut message_model = new_message_model(message, has_media);
+        // Anonymous admins and some service messages have no sender, they are attributed to
+        // the chat itself like channel posts
+        let user_id = message
+            .sender()
+            .map_or_else(|| message.chat().id(), |sender| sender.id());
+        message_model.user_id = ActiveValue::Set(user_id);
 
-        entity::prelude::Messages::insert(message_model)
+        self.insert_message(message_model).await?;
+        self.save_chat(&message.chat()).await?;
+        if let Some(sender) = message.sender() {
+            self.save_sender(&sender).await?;
+        }
+
+        info!("Saved message from chat id {}", message.chat().id());
+
+        Ok(())
+    }
+
+    pub async fn save_channel_post(
+        &self,
+        message: &Message,
+        has_media: bool,
+    ) -> anyhow::Result<()> {
+        // Channel posts have no sender, so they are attributed to the channel itself
+        let mut message_model = new_message_model(message, has_media);
+        message_model.user_id = ActiveValue::Set(message.chat().id());
+        message_model.post_author = ActiveValue::Set(message.post_author().map(str::to_string));
+        message_model.view_count = ActiveValue::Set(message.view_count());
+        message_model.forward_count = ActiveValue::Set(message.forward_count());
+
+        self.insert_message(message_model).await?;
+        self.save_chat(&message.chat()).await?;
+
+        info!("Saved post from channel id {}", message.chat().id());
+
+        Ok(())
+    }
+
+    async fn insert_message(&self, model: entity::messages::ActiveModel) -> anyhow::Result<()> {
+        entity::prelude::Messages::insert(model)
             .on_conflict(
                 sea_query::OnConflict::columns(vec![
                     entity::messages::Column::Id,
@@ -91,23 +201,515 @@ impl Db {
             .exec(&self.db)
             .await?;
 
-        info!("Saved message from chat id {}", message.chat().id());
+        Ok(())
+    }
+
+    /// Saves a chat, and for private chats the user on the other side as well.
+    pub async fn save_chat(&self, chat: &Chat) -> anyhow::Result<()> {
+        if let Chat::User(user) = chat {
+            self.save_user(user).await?;
+        }
+
+        let model = entity::chats::Model {
+            id: chat.id(),
+            kind: ChatKind::from(chat).as_str().to_string(),
+            title: chat.name().to_string(),
+            username: chat.username().map(str::to_string),
+        };
+
+        if self.chat_cache.get(&model.id).await.as_ref() == Some(&model) {
+            return Ok(());
+        }
+
+        let chat_model: entity::chats::ActiveModel = model.clone().into();
+        entity::prelude::Chats::insert(chat_model.reset_all())
+            .on_conflict(
+                sea_query::OnConflict::column(entity::chats::Column::Id)
+                    .update_columns([
+                        entity::chats::Column::Kind,
+                        entity::chats::Column::Title,
+                        entity::chats::Column::Username,
+                    ])
+                    .to_owned(),
+            )
+            .exec(&self.db)
+            .await?;
+
+        self.chat_cache.insert(model.id, model).await;
 
         Ok(())
     }
 
-    pub async fn get_last_loaded_message_id_by_chat(&self, chat_id: i64) -> anyhow::Result<i32> {
-        let msg = entity::prelude::Messages::find()
-            .filter(entity::messages::Column::ChatId.eq(chat_id))
-            .order_by_asc(entity::messages::Column::Date)
+    /// Saves a message sender, which is a user unless posting on behalf of a chat.
+    pub async fn save_sender(&self, sender: &Chat) -> anyhow::Result<()> {
+        match sender {
+            Chat::User(user) => self.save_user(user).await,
+            _ => self.save_chat(sender).await,
+        }
+    }
+
+    async fn save_user(&self, user: &User) -> anyhow::Result<()> {
+        let mut model = entity::users::Model {
+            id: user.id(),
+            first_name: user.first_name().to_string(),
+            last_name: user.last_name().map(str::to_string),
+            username: user.username().map(str::to_string),
+            phone: user.phone().map(str::to_string),
+            is_bot: user.is_bot(),
+            photo_id: user.photo().map(|photo| photo.photo_id),
+            packed: Some(user.pack().to_bytes()),
+        };
+
+        let previous = match self.user_cache.get(&model.id).await {
+            Some(previous) => Some(previous),
+            None => {
+                entity::prelude::Users::find_by_id(model.id)
+                    .one(&self.db)
+                    .await?
+            }
+        };
+
+        // Phones hidden by privacy settings and min users seen in groups come without phone and
+        // username, which doesn't mean they were removed
+        if let Some(previous) = &previous {
+            model.phone = model.phone.or_else(|| previous.phone.clone());
+            model.username = model.username.or_else(|| previous.username.clone());
+        }
+
+        if previous.as_ref() == Some(&model) {
+            self.user_cache.insert(model.id, model).await;
+            return Ok(());
+        }
+
+        let is_profile_changed = previous.as_ref().map_or(true, |previous| {
+            previous.first_name != model.first_name
+                || previous.last_name != model.last_name
+                || previous.username != model.username
+                || previous.photo_id != model.photo_id
+        });
+        if is_profile_changed {
+            self.save_user_revision(&model).await?;
+        }
+
+        let user_model: entity::users::ActiveModel = model.clone().into();
+        entity::prelude::Users::insert(user_model.reset_all())
+            .on_conflict(
+                sea_query::OnConflict::column(entity::users::Column::Id)
+                    .update_columns([
+                        entity::users::Column::FirstName,
+                        entity::users::Column::LastName,
+                        entity::users::Column::IsBot,
+                        entity::users::Column::PhotoId,
+                        entity::users::Column::Packed,
+                    ])
+                    .value(
+                        entity::users::Column::Username,
+                        Expr::cust("COALESCE(excluded.username, users.username)"),
+                    )
+                    .value(
+                        entity::users::Column::Phone,
+                        Expr::cust("COALESCE(excluded.phone, users.phone)"),
+                    )
+                    .to_owned(),
+            )
+            .exec(&self.db)
+            .await?;
+
+        self.user_cache.insert(model.id, model).await;
+
+        Ok(())
+    }
+
+    async fn save_user_revision(&self, user: &entity::users::Model) -> anyhow::Result<()> {
+        let version = entity::prelude::UserRevisions::find()
+            .filter(entity::user_revisions::Column::UserId.eq(user.id))
+            .count(&self.db)
+            .await? as i32
+            + 1;
+
+        let revision = entity::user_revisions::ActiveModel {
+            id: ActiveValue::NotSet,
+            user_id: ActiveValue::Set(user.id),
+            version: ActiveValue::Set(version),
+            first_name: ActiveValue::Set(user.first_name.clone()),
+            last_name: ActiveValue::Set(user.last_name.clone()),
+            username: ActiveValue::Set(user.username.clone()),
+            photo_id: ActiveValue::Set(user.photo_id),
+            photo_path: ActiveValue::Set(None),
+            // Nothing to download if the user has no profile photo
+            photo_downloaded: ActiveValue::Set(user.photo_id.is_none()),
+            media_id: ActiveValue::Set(None),
+            seen_at: ActiveValue::Set(Utc::now().to_string()),
+        };
+        entity::prelude::UserRevisions::insert(revision)
+            .exec(&self.db)
+            .await?;
+
+        info!("Saved profile version {} of user id {}", version, user.id);
+
+        Ok(())
+    }
+
+    pub async fn get_user_revisions(
+        &self,
+        user_id: i64,
+    ) -> anyhow::Result<Vec<entity::user_revisions::Model>> {
+        Ok(entity::prelude::UserRevisions::find()
+            .filter(entity::user_revisions::Column::UserId.eq(user_id))
+            .order_by_asc(entity::user_revisions::Column::Version)
+            .all(&self.db)
+            .await?)
+    }
+
+    /// Returns a profile photo waiting for download together with its user.
+    pub async fn get_profile_photo_not_downloaded(
+        &self,
+    ) -> anyhow::Result<Option<(entity::user_revisions::Model, entity::users::Model)>> {
+        while let Some(revision) = entity::prelude::UserRevisions::find()
+            .filter(entity::user_revisions::Column::PhotoDownloaded.eq(false))
+            .order_by_asc(entity::user_revisions::Column::Id)
             .one(&self.db)
-            .await;
+            .await?
+        {
+            let user = entity::prelude::Users::find_by_id(revision.user_id)
+                .one(&self.db)
+                .await?;
 
-        if let Some(msg) = msg? {
-            Ok(msg.id)
+            match user {
+                Some(user) => return Ok(Some((revision, user))),
+                None => self.save_profile_photo_status(revision, None).await?,
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Marks a profile photo as done, `media` is the stored photo unless it is not available.
+    pub async fn save_profile_photo_status(
+        &self,
+        model: entity::user_revisions::Model,
+        media: Option<&entity::media::Model>,
+    ) -> anyhow::Result<()> {
+        let mut revision: entity::user_revisions::ActiveModel = model.into();
+
+        revision.photo_downloaded = ActiveValue::Set(true);
+        revision.photo_path = ActiveValue::Set(media.and_then(|media| media.path.clone()));
+        revision.media_id = ActiveValue::Set(media.map(|media| media.id));
+
+        revision.update(&self.db).await?;
+        Ok(())
+    }
+
+    /// Applies an edit to an already archived message, keeping the replaced version as a
+    /// revision. Returns `false` if the message is not archived yet.
+    pub async fn save_message_edit(
+        &self,
+        message: &Message,
+        has_media: bool,
+    ) -> anyhow::Result<bool> {
+        let model = entity::prelude::Messages::find_by_id((message.id(), message.chat().id()))
+            .one(&self.db)
+            .await?;
+
+        let Some(model) = model else {
+            return Ok(false);
+        };
+
+        let edit_date = message.edit_date().map(|date| date.to_string());
+        let media_file_id = if has_media {
+            message.media().as_ref().and_then(get_media_file_id)
         } else {
-            Err(anyhow!("Possible missing chat: {}", chat_id))
+            None
+        };
+        let media_changed =
+            has_media != model.has_binary_data || media_file_id != model.media_file_id;
+
+        let media_data = media_data_to_json(message);
+        let web_page = web_page_to_json(message);
+        // A link preview with a photo counts as media, but it is not part of the edit
+        let is_web_page = web_page.is_some() || model.web_page.is_some();
+
+        if edit_date == model.edit_date
+            && message.text() == model.text
+            && get_message_entities(message) == model.entities
+            && (!media_changed || is_web_page)
+        {
+            // Live locations move, polls get closed and link previews get resolved without any
+            // visible edit
+            if media_data != model.media_data || web_page != model.web_page || media_changed {
+                let previous_media_id = model.media_id.filter(|_| media_changed);
+                let mut message_model: entity::messages::ActiveModel = model.into();
+                message_model.media_data = ActiveValue::Set(media_data);
+                message_model.web_page = ActiveValue::Set(web_page);
+                if media_changed {
+                    reset_message_media(&mut message_model, has_media, media_file_id);
+                }
+                message_model.update(&self.db).await?;
+
+                if let Some(previous_media_id) = previous_media_id {
+                    self.release_media(previous_media_id).await?;
+                }
+                return Ok(true);
+            }
+
+            debug!(
+                "Edit of message {} in chat {} is already saved, skipping...",
+                message.id(),
+                message.chat().id()
+            );
+            return Ok(true);
+        }
+
+        let revision = entity::message_revisions::ActiveModel {
+            id: ActiveValue::NotSet,
+            message_id: ActiveValue::Set(model.id),
+            chat_id: ActiveValue::Set(model.chat_id),
+            revision: ActiveValue::Set(model.edit_count),
+            text: ActiveValue::Set(model.text.clone()),
+            date: ActiveValue::Set(model.edit_date.clone().unwrap_or(model.date.clone())),
+            replaced_at: ActiveValue::Set(edit_date.clone()),
+            has_binary_data: ActiveValue::Set(model.has_binary_data),
+            binary_data_path: ActiveValue::Set(model.binary_data_path.clone()),
+            binary_data_type: ActiveValue::Set(model.binary_data_type.clone()),
+            media_file_id: ActiveValue::Set(model.media_file_id),
+            media_changed: ActiveValue::Set(media_changed),
+            entities: ActiveValue::Set(model.entities.clone()),
+        };
+        entity::prelude::MessageRevisions::insert(revision)
+            .exec(&self.db)
+            .await?;
+
+        let edit_count = model.edit_count + 1;
+        let mut message_model: entity::messages::ActiveModel = model.into();
+        message_model.text = ActiveValue::Set(message.text().to_string());
+        message_model.entities = ActiveValue::Set(get_message_entities(message));
+        message_model.media_data = ActiveValue::Set(media_data);
+        message_model.web_page = ActiveValue::Set(web_page);
+        message_model.edit_date = ActiveValue::Set(edit_date);
+        message_model.edit_count = ActiveValue::Set(edit_count);
+
+        // The previous file stays referenced by the revision, which keeps its reference count,
+        // the new one gets downloaded anew
+        if media_changed {
+            reset_message_media(&mut message_model, has_media, media_file_id);
+        }
+
+        message_model.update(&self.db).await?;
+
+        info!(
+            "Saved edit #{} of message {} from chat id {}",
+            edit_count,
+            message.id(),
+            message.chat().id()
+        );
+
+        Ok(true)
+    }
+
+    pub async fn get_message(
+        &self,
+        chat_id: i64,
+        message_id: i32,
+    ) -> anyhow::Result<Option<entity::messages::Model>> {
+        Ok(entity::prelude::Messages::find_by_id((message_id, chat_id))
+            .one(&self.db)
+            .await?)
+    }
+
+    pub async fn get_message_revisions(
+        &self,
+        message_id: i32,
+        chat_id: i64,
+    ) -> anyhow::Result<Vec<entity::message_revisions::Model>> {
+        Ok(entity::prelude::MessageRevisions::find()
+            .filter(entity::message_revisions::Column::MessageId.eq(message_id))
+            .filter(entity::message_revisions::Column::ChatId.eq(chat_id))
+            .order_by_asc(entity::message_revisions::Column::Revision)
+            .all(&self.db)
+            .await?)
+    }
+
+    /// Marks messages as deleted while keeping their content. Telegram only reports the
+    /// channel for channel and supergroup deletions, other message ids are unique per account.
+    pub async fn mark_messages_deleted(
+        &self,
+        channel_id: Option<i64>,
+        message_ids: &[i32],
+    ) -> anyhow::Result<u64> {
+        let mut query = entity::prelude::Messages::update_many()
+            .col_expr(
+                entity::messages::Column::DeletedAt,
+                Expr::value(Utc::now().to_string()),
+            )
+            .filter(entity::messages::Column::Id.is_in(message_ids.iter().copied()))
+            .filter(entity::messages::Column::DeletedAt.is_null());
+
+        query = match channel_id {
+            Some(channel_id) => query.filter(entity::messages::Column::ChatId.eq(channel_id)),
+            None => query.filter(
+                entity::messages::Column::ChatKind
+                    .is_in([ChatKind::User.as_str(), ChatKind::Group.as_str()]),
+            ),
+        };
+
+        let result = query.exec(&self.db).await?;
+
+        if result.rows_affected > 0 {
+            info!(
+                "Marked {} message(s) as deleted (channel id {:?})",
+                result.rows_affected, channel_id
+            );
+        }
+
+        Ok(result.rows_affected)
+    }
+
+    /// Marks messages that no longer exist on the server as deleted while teledump was offline.
+    pub async fn mark_messages_deleted_offline(
+        &self,
+        chat_id: i64,
+        message_ids: &[i32],
+    ) -> anyhow::Result<u64> {
+        let deleted_at = Utc::now().to_string();
+        let mut rows_affected = 0;
+
+        // Keep well below SQLite's bound parameters limit
+        for message_ids in message_ids.chunks(500) {
+            let result = entity::prelude::Messages::update_many()
+                .col_expr(
+                    entity::messages::Column::DeletedAt,
+                    Expr::value(deleted_at.clone()),
+                )
+                .col_expr(entity::messages::Column::DeletedOffline, Expr::value(true))
+                .filter(entity::messages::Column::ChatId.eq(chat_id))
+                .filter(entity::messages::Column::Id.is_in(message_ids.iter().copied()))
+                .filter(entity::messages::Column::DeletedAt.is_null())
+                .exec(&self.db)
+                .await?;
+
+            rows_affected += result.rows_affected;
+        }
+
+        Ok(rows_affected)
+    }
+
+    pub async fn get_present_message_ids_by_chat(&self, chat_id: i64) -> anyhow::Result<Vec<i32>> {
+        Ok(entity::prelude::Messages::find()
+            .select_only()
+            .column(entity::messages::Column::Id)
+            .filter(entity::messages::Column::ChatId.eq(chat_id))
+            .filter(entity::messages::Column::DeletedAt.is_null())
+            .into_tuple::<i32>()
+            .all(&self.db)
+            .await?)
+    }
+
+    /// Ids of every archived message in a chat, deleted ones included.
+    pub async fn get_message_ids_by_chat(&self, chat_id: i64) -> anyhow::Result<Vec<i32>> {
+        Ok(entity::prelude::Messages::find()
+            .select_only()
+            .column(entity::messages::Column::Id)
+            .filter(entity::messages::Column::ChatId.eq(chat_id))
+            .into_tuple::<i32>()
+            .all(&self.db)
+            .await?)
+    }
+
+    pub async fn get_deleted_messages_by_chat(
+        &self,
+        chat_id: i64,
+    ) -> anyhow::Result<Vec<entity::messages::Model>> {
+        Ok(entity::prelude::Messages::find()
+            .filter(entity::messages::Column::ChatId.eq(chat_id))
+            .filter(entity::messages::Column::DeletedAt.is_not_null())
+            .order_by_asc(entity::messages::Column::Date)
+            .order_by_asc(entity::messages::Column::Id)
+            .all(&self.db)
+            .await?)
+    }
+
+    /// All messages of an album, in the order they were sent.
+    pub async fn get_album_messages(
+        &self,
+        chat_id: i64,
+        grouped_id: i64,
+    ) -> anyhow::Result<Vec<entity::messages::Model>> {
+        Ok(entity::prelude::Messages::find()
+            .filter(entity::messages::Column::ChatId.eq(chat_id))
+            .filter(entity::messages::Column::GroupedId.eq(grouped_id))
+            .order_by_asc(entity::messages::Column::Id)
+            .all(&self.db)
+            .await?)
+    }
+
+    /// Chats with archived messages, only those in `chat_ids` unless it is empty.
+    pub async fn get_archived_chat_ids(&self, chat_ids: &[i64]) -> anyhow::Result<Vec<i64>> {
+        let mut query = entity::prelude::Messages::find()
+            .select_only()
+            .column(entity::messages::Column::ChatId)
+            .distinct()
+            .order_by_asc(entity::messages::Column::ChatId);
+
+        if !chat_ids.is_empty() {
+            query = query.filter(entity::messages::Column::ChatId.is_in(chat_ids.iter().copied()));
+        }
+
+        Ok(query.into_tuple::<i64>().all(&self.db).await?)
+    }
+
+    /// Every message of a chat in the order it was sent, deleted ones included.
+    pub async fn get_messages_by_chat(
+        &self,
+        chat_id: i64,
+    ) -> anyhow::Result<Vec<entity::messages::Model>> {
+        Ok(entity::prelude::Messages::find()
+            .filter(entity::messages::Column::ChatId.eq(chat_id))
+            .order_by_asc(entity::messages::Column::Date)
+            .order_by_asc(entity::messages::Column::Id)
+            .all(&self.db)
+            .await?)
+    }
+
+    pub async fn get_chats_by_ids(&self, ids: &[i64]) -> anyhow::Result<Vec<entity::chats::Model>> {
+        let mut chats = vec![];
+        for ids in ids.chunks(500) {
+            chats.extend(
+                entity::prelude::Chats::find()
+                    .filter(entity::chats::Column::Id.is_in(ids.iter().copied()))
+                    .all(&self.db)
+                    .await?,
+            );
+        }
+
+        Ok(chats)
+    }
+
+    pub async fn get_users_by_ids(&self, ids: &[i64]) -> anyhow::Result<Vec<entity::users::Model>> {
+        let mut users = vec![];
+        for ids in ids.chunks(500) {
+            users.extend(
+                entity::prelude::Users::find()
+                    .filter(entity::users::Column::Id.is_in(ids.iter().copied()))
+                    .all(&self.db)
+                    .await?,
+            );
+        }
+
+        Ok(users)
+    }
+
+    pub async fn get_media_by_ids(&self, ids: &[i32]) -> anyhow::Result<Vec<entity::media::Model>> {
+        let mut media = vec![];
+        for ids in ids.chunks(500) {
+            media.extend(
+                entity::prelude::Media::find()
+                    .filter(entity::media::Column::Id.is_in(ids.iter().copied()))
+                    .all(&self.db)
+                    .await?,
+            );
         }
+
+        Ok(media)
     }
 
     pub async fn get_last_message_by_chat(
@@ -127,19 +729,103 @@ impl Db {
         }
     }
 
-    pub async fn get_message_with_media_not_downloaded(&self) -> Option<entity::messages::Model> {
-        let message = entity::prelude::Messages::find()
+    /// Returns up to `batch_size` messages with pending media, interleaved by chat so that a
+    /// single busy chat does not hold up the others.
+    pub async fn get_messages_with_media_not_downloaded(
+        &self,
+        batch_size: usize,
+    ) -> anyhow::Result<Vec<entity::messages::Model>> {
+        let pending = || {
+            entity::prelude::Messages::find()
+                .filter(entity::messages::Column::HasBinaryData.eq(true))
+                .filter(entity::messages::Column::BinaryDataDownloaded.eq(false))
+                .filter(entity::messages::Column::BinaryDataReferenceExpired.eq(false))
+                .filter(entity::messages::Column::BinaryDataSkipped.eq(false))
+                .filter(entity::messages::Column::DeletedAt.is_null())
+        };
+
+        // Chats whose media was tried least recently come first, so that a few chats with
+        // failing media don't starve the others
+        let chat_ids = pending()
+            .select_only()
+            .column(entity::messages::Column::ChatId)
+            .group_by(entity::messages::Column::ChatId)
+            .order_by(
+                Expr::col(entity::messages::Column::BinaryDataAttemptedAt).max(),
+                sea_query::Order::Asc,
+            )
+            .order_by_asc(entity::messages::Column::ChatId)
+            .limit(batch_size as u64)
+            .into_tuple::<i64>()
+            .all(&self.db)
+            .await?;
+
+        if chat_ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let per_chat = (batch_size / chat_ids.len()).max(1) as u64;
+        let mut queues = vec![];
+        for chat_id in chat_ids {
+            let messages = pending()
+                .filter(entity::messages::Column::ChatId.eq(chat_id))
+                .order_by_asc(entity::messages::Column::Id)
+                .limit(per_chat)
+                .all(&self.db)
+                .await?;
+            queues.push(messages.into_iter());
+        }
+
+        let mut batch = Vec::with_capacity(batch_size);
+        while batch.len() < batch_size {
+            let mut taken = false;
+            for queue in queues.iter_mut() {
+                if let Some(message) = queue.next() {
+                    batch.push(message);
+                    taken = true;
+                }
+            }
+
+            if !taken {
+                break;
+            }
+        }
+        batch.truncate(batch_size);
+
+        Ok(batch)
+    }
+
+    pub async fn save_message_media_attempt(
+        &self,
+        model: &entity::messages::Model,
+    ) -> anyhow::Result<()> {
+        entity::prelude::Messages::update_many()
+            .col_expr(
+                entity::messages::Column::BinaryDataAttemptedAt,
+                Expr::value(Utc::now().to_string()),
+            )
+            .filter(entity::messages::Column::Id.eq(model.id))
+            .filter(entity::messages::Column::ChatId.eq(model.chat_id))
+            .exec(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_messages_with_expired_media(
+        &self,
+        chat_ids: &[i64],
+    ) -> anyhow::Result<Vec<entity::messages::Model>> {
+        let mut query = entity::prelude::Messages::find()
             .filter(entity::messages::Column::HasBinaryData.eq(true))
-            .filter(entity::messages::Column::BinaryDataDownloaded.eq(false))
-            .filter(entity::messages::Column::BinaryDataReferenceExpired.eq(false))
-            .one(&self.db)
-            .await;
+            .filter(entity::messages::Column::BinaryDataReferenceExpired.eq(true))
+            .filter(entity::messages::Column::DeletedAt.is_null());
 
-        if let Ok(msg) = message {
-            msg
-        } else {
-            None
+        if !chat_ids.is_empty() {
+            query = query.filter(entity::messages::Column::ChatId.is_in(chat_ids.iter().copied()));
         }
+
+        Ok(query.all(&self.db).await?)
     }
 
     pub async fn save_message_media_status(
@@ -161,10 +847,562 @@ impl Db {
         Ok(())
     }
 
+    /// Finds or creates the media row for a file, with the metadata Telegram reports for it.
+    /// A message still pointing at a legacy row without file key has that row adopted instead.
+    pub async fn save_media(
+        &self,
+        media: &Media,
+        media_kind: &str,
+        legacy_media_id: Option<i32>,
+    ) -> anyhow::Result<entity::media::Model> {
+        let model = new_media_model(media, media_kind);
+        let file_key = get_media_file_key(media);
+
+        if let Some(file_key) = &file_key {
+            let existing = entity::prelude::Media::find()
+                .filter(entity::media::Column::FileKey.eq(file_key.as_str()))
+                .one(&self.db)
+                .await?;
+            if let Some(existing) = existing {
+                return Ok(existing);
+            }
+        }
+
+        if let Some(legacy_media_id) = legacy_media_id {
+            let legacy = entity::prelude::Media::find_by_id(legacy_media_id)
+                .filter(entity::media::Column::FileKey.is_null())
+                .one(&self.db)
+                .await?;
+            if let Some(legacy) = legacy {
+                let mut model = model;
+                model.id = ActiveValue::Unchanged(legacy.id);
+                model.sha256 = ActiveValue::Set(legacy.sha256);
+                model.path = ActiveValue::Set(legacy.path);
+                model.state = ActiveValue::Set(legacy.state);
+                model.attempts = ActiveValue::Set(legacy.attempts);
+                model.last_error = ActiveValue::Set(legacy.last_error);
+                model.ref_count = ActiveValue::Set(legacy.ref_count);
+
+                return Ok(model.update(&self.db).await?);
+            }
+        }
+
+        let Some(file_key) = file_key else {
+            return Ok(model.insert(&self.db).await?);
+        };
+
+        // Messages sharing a file are processed concurrently, whoever comes first inserts it
+        entity::prelude::Media::insert(model)
+            .on_conflict(
+                sea_query::OnConflict::column(entity::media::Column::FileKey)
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .exec_without_returning(&self.db)
+            .await?;
+
+        entity::prelude::Media::find()
+            .filter(entity::media::Column::FileKey.eq(file_key.as_str()))
+            .one(&self.db)
+            .await?
+            .ok_or_else(|| anyhow!("Media {} vanished right after saving it", file_key))
+    }
+
+    pub async fn get_media(&self, id: i32) -> anyhow::Result<Option<entity::media::Model>> {
+        Ok(entity::prelude::Media::find_by_id(id).one(&self.db).await?)
+    }
+
+    /// Another downloaded file with the same content, whose blob can be shared.
+    pub async fn get_downloaded_media_by_sha256(
+        &self,
+        sha256: &str,
+    ) -> anyhow::Result<Option<entity::media::Model>> {
+        Ok(entity::prelude::Media::find()
+            .filter(entity::media::Column::Sha256.eq(sha256))
+            .filter(entity::media::Column::State.eq(MediaState::Downloaded.as_str()))
+            .filter(entity::media::Column::Path.is_not_null())
+            .one(&self.db)
+            .await?)
+    }
+
+    pub async fn save_media_downloaded(
+        &self,
+        model: entity::media::Model,
+        sha256: String,
+        path: String,
+        size: i64,
+    ) -> anyhow::Result<entity::media::Model> {
+        let attempts = model.attempts + 1;
+        let mut media: entity::media::ActiveModel = model.into();
+
+        media.sha256 = ActiveValue::Set(Some(sha256));
+        media.path = ActiveValue::Set(Some(path));
+        media.size = ActiveValue::Set(Some(size));
+        media.state = ActiveValue::Set(MediaState::Downloaded.as_str().to_string());
+        media.attempts = ActiveValue::Set(attempts);
+        media.last_error = ActiveValue::Set(None);
+
+        Ok(media.update(&self.db).await?)
+    }
+
+    /// Counts a failed download attempt and remembers why it failed.
+    pub async fn save_media_failure(
+        &self,
+        model: &entity::media::Model,
+        error: &str,
+    ) -> anyhow::Result<()> {
+        entity::prelude::Media::update_many()
+            .col_expr(
+                entity::media::Column::Attempts,
+                Expr::col(entity::media::Column::Attempts).add(1),
+            )
+            .col_expr(entity::media::Column::LastError, Expr::value(error))
+            .filter(entity::media::Column::Id.eq(model.id))
+            .exec(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn save_media_state(
+        &self,
+        model: &entity::media::Model,
+        state: MediaState,
+    ) -> anyhow::Result<()> {
+        entity::prelude::Media::update_many()
+            .col_expr(entity::media::Column::State, Expr::value(state.as_str()))
+            .filter(entity::media::Column::Id.eq(model.id))
+            .exec(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Points a message at its media row, counting the reference once per message. A row the
+    /// message pointed at before loses its reference, unless an edit moved it to a revision.
+    pub async fn attach_message_media(
+        &self,
+        model: entity::messages::Model,
+        media: &entity::media::Model,
+    ) -> anyhow::Result<entity::messages::Model> {
+        if model.media_id == Some(media.id) {
+            return Ok(model);
+        }
+
+        let previous_media_id = model.media_id;
+        let mut message: entity::messages::ActiveModel = model.into();
+        message.media_id = ActiveValue::Set(Some(media.id));
+        let message = message.update(&self.db).await?;
+
+        if let Some(previous_media_id) = previous_media_id {
+            self.release_media(previous_media_id).await?;
+        }
+
+        entity::prelude::Media::update_many()
+            .col_expr(
+                entity::media::Column::RefCount,
+                Expr::col(entity::media::Column::RefCount).add(1),
+            )
+            .filter(entity::media::Column::Id.eq(media.id))
+            .exec(&self.db)
+            .await?;
+
+        Ok(message)
+    }
+
+    async fn release_media(&self, media_id: i32) -> anyhow::Result<()> {
+        entity::prelude::Media::update_many()
+            .col_expr(
+                entity::media::Column::RefCount,
+                Expr::col(entity::media::Column::RefCount).sub(1),
+            )
+            .filter(entity::media::Column::Id.eq(media_id))
+            .filter(entity::media::Column::RefCount.gt(0))
+            .exec(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn save_message_media_skipped(
+        &self,
+        model: entity::messages::Model,
+    ) -> anyhow::Result<()> {
+        let mut message: entity::messages::ActiveModel = model.into();
+
+        message.binary_data_skipped = ActiveValue::Set(true);
+
+        message.update(&self.db).await?;
+        Ok(())
+    }
+
+    /// Queues media skipped by policy for download again, in every chat if `chat_ids` is empty.
+    pub async fn reset_skipped_media(&self, chat_ids: &[i64]) -> anyhow::Result<u64> {
+        let mut query = entity::prelude::Messages::update_many()
+            .col_expr(
+                entity::messages::Column::BinaryDataSkipped,
+                Expr::value(false),
+            )
+            .filter(entity::messages::Column::BinaryDataSkipped.eq(true));
+
+        if !chat_ids.is_empty() {
+            query = query.filter(entity::messages::Column::ChatId.is_in(chat_ids.iter().copied()));
+        }
+
+        Ok(query.exec(&self.db).await?.rows_affected)
+    }
+
     pub async fn get_message_count_by_chat(&self, chat_id: i64) -> anyhow::Result<usize> {
         Ok(entity::messages::Entity::find()
             .filter(entity::messages::Column::ChatId.eq(chat_id))
             .count(&self.db)
             .await? as usize)
     }
+
+    /// Saves a message imported from an export, returns `false` if it was already archived.
+    pub async fn save_imported_message(
+        &self,
+        model: entity::messages::Model,
+    ) -> anyhow::Result<bool> {
+        let message: entity::messages::ActiveModel = model.into();
+        let rows_affected = entity::prelude::Messages::insert(message.reset_all())
+            .on_conflict(
+                sea_query::OnConflict::columns(vec![
+                    entity::messages::Column::Id,
+                    entity::messages::Column::ChatId,
+                ])
+                .do_nothing()
+                .to_owned(),
+            )
+            .exec_without_returning(&self.db)
+            .await?;
+
+        Ok(rows_affected > 0)
+    }
+
+    /// Saves a chat known only from an export, keeping what was archived about it.
+    pub async fn save_imported_chat(
+        &self,
+        id: i64,
+        kind: ChatKind,
+        title: &str,
+    ) -> anyhow::Result<()> {
+        let chat = entity::chats::ActiveModel {
+            id: ActiveValue::Set(id),
+            kind: ActiveValue::Set(kind.as_str().to_string()),
+            title: ActiveValue::Set(title.to_string()),
+            username: ActiveValue::Set(None),
+        };
+
+        entity::prelude::Chats::insert(chat)
+            .on_conflict(
+                sea_query::OnConflict::column(entity::chats::Column::Id)
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .exec_without_returning(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Saves a user known only by the name shown in an export, keeping what was archived.
+    pub async fn save_imported_user(&self, id: i64, name: &str) -> anyhow::Result<()> {
+        let user = entity::users::ActiveModel {
+            id: ActiveValue::Set(id),
+            first_name: ActiveValue::Set(name.to_string()),
+            last_name: ActiveValue::Set(None),
+            username: ActiveValue::Set(None),
+            phone: ActiveValue::Set(None),
+            is_bot: ActiveValue::Set(false),
+            photo_id: ActiveValue::Set(None),
+            packed: ActiveValue::Set(None),
+        };
+
+        entity::prelude::Users::insert(user)
+            .on_conflict(
+                sea_query::OnConflict::column(entity::users::Column::Id)
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .exec_without_returning(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn insert_media(
+        &self,
+        model: entity::media::ActiveModel,
+    ) -> anyhow::Result<entity::media::Model> {
+        Ok(model.insert(&self.db).await?)
+    }
+
+    /// Finds messages matching a search, best matches first.
+    pub async fn search_messages(
+        &self,
+        search: &MessageSearch<'_>,
+    ) -> anyhow::Result<Vec<SearchResult>> {
+        let mut sql = String::from(
+            "SELECT messages.chat_id, messages.id, messages.user_id, messages.date,
+                    messages.deleted_at,
+                    snippet(messages_fts, 0, ?, ?, '…', 16) AS snippet
+             FROM messages_fts
+             JOIN messages ON messages.chat_id = messages_fts.chat_id
+                 AND messages.id = messages_fts.message_id
+             WHERE messages_fts MATCH ?",
+        );
+        let mut values: Vec<Value> = vec![
+            search.highlight.0.into(),
+            search.highlight.1.into(),
+            search.query.into(),
+        ];
+
+        for (column, ids) in [("chat_id", search.chat_ids), ("user_id", search.senders)] {
+            if ids.is_empty() {
+                continue;
+            }
+            let placeholders = vec!["?"; ids.len()].join(", ");
+            sql.push_str(&format!(" AND messages.{} IN ({})", column, placeholders));
+            values.extend(ids.iter().map(|id| Value::from(*id)));
+        }
+        // Dates are stored in a fixed format that sorts chronologically
+        if let Some(since) = search.since {
+            sql.push_str(" AND messages.date >= ?");
+            values.push(since.to_string().into());
+        }
+        if let Some(until) = search.until {
+            sql.push_str(" AND messages.date < ?");
+            values.push(until.to_string().into());
+        }
+
+        sql.push_str(" ORDER BY rank LIMIT ?");
+        values.push(search.limit.into());
+
+        SearchResult::find_by_statement(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            sql,
+            values,
+        ))
+        .all(&self.db)
+        .await
+        .map_err(|e| {
+            let error = e.to_string();
+            // Malformed queries are plain SQL errors, e.g. `foo-bar` reads as a column filter
+            if [
+                "fts5: syntax error",
+                "unterminated string",
+                "no such column",
+            ]
+            .iter()
+            .any(|syntax_error| error.contains(syntax_error))
+            {
+                anyhow!(
+                    "Invalid search query `{}`: {}\nPut words with special characters in \
+                     double quotes, e.g. '\"foo-bar\"', and close every quote and parenthesis.",
+                    search.query,
+                    error
+                )
+            } else {
+                anyhow!("Search for `{}` failed: {}", search.query, e)
+            }
+        })
+    }
+}
+
+fn new_message_model(message: &Message, has_media: bool) -> entity::messages::ActiveModel {
+    let forward_header = message
+        .forward_header()
+        .map(|tl::enums::MessageFwdHeader::Header(header)| header);
+
+    entity::messages::ActiveModel {
+        id: ActiveValue::set(message.id()),
+        chat_id: ActiveValue::Set(message.chat().id()),
+        user_id: ActiveValue::NotSet,
+        text: ActiveValue::Set(message.text().to_string()),
+        has_binary_data: ActiveValue::Set(has_media),
+        binary_data_downloaded: ActiveValue::Set(false),
+        binary_data_path: ActiveValue::Set(None),
+        binary_data_type: ActiveValue::Set(None),
+        binary_data_reference_expired: ActiveValue::Set(false),
+        date: ActiveValue::Set(message.date().to_string()),
+        chat_kind: ActiveValue::Set(ChatKind::from(&message.chat()).as_str().to_string()),
+        post_author: ActiveValue::Set(None),
+        view_count: ActiveValue::Set(None),
+        forward_count: ActiveValue::Set(None),
+        edit_date: ActiveValue::Set(message.edit_date().map(|date| date.to_string())),
+        edit_count: ActiveValue::Set(0),
+        deleted_at: ActiveValue::Set(None),
+        deleted_offline: ActiveValue::Set(false),
+        media_file_id: ActiveValue::Set(if has_media {
+            message.media().as_ref().and_then(get_media_file_id)
+        } else {
+            None
+        }),
+        reply_to_message_id: ActiveValue::Set(message.reply_to_message_id()),
+        forward_from_id: ActiveValue::Set(
+            forward_header
+                .as_ref()
+                .and_then(|header| header.from_id.as_ref())
+                .map(get_peer_id),
+        ),
+        forward_from_name: ActiveValue::Set(
+            forward_header
+                .as_ref()
+                .and_then(|header| header.from_name.clone()),
+        ),
+        forward_date: ActiveValue::Set(forward_header.as_ref().map(|header| {
+            Utc.timestamp_opt(header.date as i64, 0)
+                .unwrap()
+                .to_string()
+        })),
+        forward_message_id: ActiveValue::Set(
+            forward_header
+                .as_ref()
+                .and_then(|header| header.channel_post),
+        ),
+        entities: ActiveValue::Set(get_message_entities(message)),
+        binary_data_skipped: ActiveValue::Set(false),
+        media_id: ActiveValue::Set(None),
+        grouped_id: ActiveValue::Set(message.grouped_id()),
+        media_data: ActiveValue::Set(media_data_to_json(message)),
+        web_page: ActiveValue::Set(web_page_to_json(message)),
+        binary_data_attempted_at: ActiveValue::Set(None),
+    }
+}
+
+/// Points a message at new media, which gets downloaded anew.
+fn reset_message_media(
+    message_model: &mut entity::messages::ActiveModel,
+    has_media: bool,
+    media_file_id: Option<i64>,
+) {
+    message_model.has_binary_data = ActiveValue::Set(has_media);
+    message_model.binary_data_downloaded = ActiveValue::Set(false);
+    message_model.binary_data_path = ActiveValue::Set(None);
+    message_model.binary_data_type = ActiveValue::Set(None);
+    message_model.binary_data_reference_expired = ActiveValue::Set(false);
+    message_model.binary_data_skipped = ActiveValue::Set(false);
+    message_model.media_id = ActiveValue::Set(None);
+    message_model.media_file_id = ActiveValue::Set(media_file_id);
+}
+
+fn get_message_entities(message: &Message) -> Option<String> {
+    message
+        .fmt_entities()
+        .and_then(|entities| formatting::entities_to_json(entities))
+}
+
+fn get_peer_id(peer: &tl::enums::Peer) -> i64 {
+    match peer {
+        tl::enums::Peer::User(user) => user.user_id,
+        tl::enums::Peer::Chat(chat) => chat.chat_id,
+        tl::enums::Peer::Channel(channel) => channel.channel_id,
+    }
+}
+
+fn new_media_model(media: &Media, media_kind: &str) -> entity::media::ActiveModel {
+    let mut model = entity::media::ActiveModel {
+        id: ActiveValue::NotSet,
+        file_key: ActiveValue::Set(get_media_file_key(media)),
+        file_id: ActiveValue::Set(get_media_file_id(media)),
+        kind: ActiveValue::Set(Some(media_kind.to_string())),
+        mime_type: ActiveValue::Set(None),
+        file_name: ActiveValue::Set(None),
+        size: ActiveValue::Set(None),
+        width: ActiveValue::Set(None),
+        height: ActiveValue::Set(None),
+        duration: ActiveValue::Set(None),
+        sha256: ActiveValue::Set(None),
+        path: ActiveValue::Set(None),
+        state: ActiveValue::Set(MediaState::Pending.as_str().to_string()),
+        attempts: ActiveValue::Set(0),
+        last_error: ActiveValue::Set(None),
+        ref_count: ActiveValue::Set(0),
+    };
+
+    match media {
+        Media::Photo(photo) => {
+            model.mime_type = ActiveValue::Set(Some("image/jpeg".to_string()));
+
+            let sizes = match &photo.raw.photo {
+                Some(tl::enums::Photo::Photo(photo)) => photo.sizes.as_slice(),
+                _ => &[],
+            };
+            // The largest size is the one that gets downloaded
+            let largest = sizes
+                .iter()
+                .filter_map(|size| match size {
+                    tl::enums::PhotoSize::Size(size) => Some((size.w, size.h, size.size)),
+                    tl::enums::PhotoSize::Progressive(size) => Some((
+                        size.w,
+                        size.h,
+                        size.sizes.iter().copied().max().unwrap_or_default(),
+                    )),
+                    _ => None,
+                })
+                .max_by_key(|(w, h, _)| w * h);
+            if let Some((width, height, size)) = largest {
+                model.width = ActiveValue::Set(Some(width));
+                model.height = ActiveValue::Set(Some(height));
+                model.size = ActiveValue::Set(Some(size as i64));
+            }
+        }
+        Media::Document(document) => set_document_metadata(&mut model, document),
+        Media::Sticker(sticker) => set_document_metadata(&mut model, &sticker.document),
+        _ => {}
+    }
+
+    model
+}
+
+fn set_document_metadata(model: &mut entity::media::ActiveModel, document: &Document) {
+    use tl::enums::DocumentAttribute as A;
+
+    model.mime_type = ActiveValue::Set(document.mime_type().map(str::to_string));
+    model.file_name =
+        ActiveValue::Set(Some(document.name().to_string()).filter(|name| !name.is_empty()));
+    model.size = ActiveValue::Set(Some(document.size()));
+
+    let attributes = match &document.raw.document {
+        Some(tl::enums::Document::Document(document)) => document.attributes.as_slice(),
+        _ => &[],
+    };
+    for attribute in attributes {
+        match attribute {
+            A::ImageSize(size) => {
+                model.width = ActiveValue::Set(Some(size.w));
+                model.height = ActiveValue::Set(Some(size.h));
+            }
+            A::Video(video) => {
+                model.width = ActiveValue::Set(Some(video.w));
+                model.height = ActiveValue::Set(Some(video.h));
+                model.duration = ActiveValue::Set(Some(video.duration as i32));
+            }
+            A::Audio(audio) => {
+                model.duration = ActiveValue::Set(Some(audio.duration));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Key identifying a file on Telegram's side, shared by every message carrying that file.
+fn get_media_file_key(media: &Media) -> Option<String> {
+    let prefix = match media {
+        Media::Photo(_) => "photo",
+        Media::Document(_) | Media::Sticker(_) => "document",
+        _ => return None,
+    };
+
+    get_media_file_id(media).map(|id| format!("{}-{}", prefix, id))
+}
+
+fn get_media_file_id(media: &Media) -> Option<i64> {
+    match media {
+        Media::Photo(photo) => Some(photo.id()),
+        Media::Document(document) => Some(document.id()),
+        Media::Sticker(sticker) => Some(sticker.document.id()),
+        _ => None,
+    }
 }
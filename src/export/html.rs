@@ -0,0 +1,502 @@
+//! Static HTML export, one folder per chat with paginated, self-contained pages.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+
+use super::{parse_date, strip_dir, ChatExport, ExportFilter, NameHistory};
+use crate::album::{group_posts, Post};
+use crate::db::Db;
+use crate::formatting::{entities_from_json, escape_html, is_safe_url, render_html};
+use crate::media_data::{media_data_from_json, MediaData};
+use crate::web_page::web_page_from_json;
+
+/// Posts per page, albums count as a single post.
+const PAGE_SIZE: usize = 1000;
+
+const STYLE: &str = r#"
+body { margin: 0; background: #e6ebee; font: 14px/1.4 -apple-system, "Segoe UI", Roboto, sans-serif; color: #000; }
+header, nav, main { max-width: 720px; margin: 0 auto; padding: 8px 16px; }
+header h1 { font-size: 18px; margin: 8px 0; }
+nav { color: #707579; }
+nav a, header a { color: #168acd; text-decoration: none; }
+.day { text-align: center; margin: 16px 0 8px; color: #fff; }
+.day span { background: rgba(0, 0, 0, .3); border-radius: 12px; padding: 2px 10px; }
+.message { background: #fff; border-radius: 12px; margin: 6px 0; padding: 8px 12px; box-shadow: 0 1px 1px rgba(0, 0, 0, .1); word-wrap: break-word; }
+.message.deleted { background: #fdeaea; }
+.message:target { outline: 2px solid #168acd; }
+.from { font-weight: bold; color: #3a8ed1; }
+.reply, .forwarded { display: block; border-left: 2px solid #3a8ed1; padding-left: 8px; margin: 4px 0; color: #707579; text-decoration: none; }
+.media img, .media video, .preview img { max-width: 100%; max-height: 480px; border-radius: 6px; display: block; margin: 4px 0; }
+.media .missing { color: #a0a4a7; font-style: italic; }
+.preview { border-left: 2px solid #3a8ed1; padding-left: 8px; margin: 4px 0; }
+.preview .site { color: #3a8ed1; font-weight: bold; }
+.text { white-space: pre-wrap; }
+.spoiler { background: #c8ccd0; color: transparent; }
+.spoiler:hover { color: inherit; }
+blockquote { margin: 4px 0; padding-left: 8px; border-left: 2px solid #c8ccd0; }
+pre, code { font-family: monospace; background: #f4f4f5; border-radius: 4px; }
+.meta { text-align: right; color: #a0a4a7; font-size: 12px; }
+.chats a { display: block; background: #fff; border-radius: 12px; margin: 6px 0; padding: 8px 12px; color: #000; text-decoration: none; }
+.chats .count { color: #a0a4a7; float: right; }
+"#;
+
+pub async fn export(
+    db: &Db,
+    chat_ids: &[i64],
+    filter: &ExportFilter,
+    output: &Path,
+) -> anyhow::Result<()> {
+    if let [chat_id] = chat_ids {
+        let chat = ChatExport::load(db, *chat_id, filter).await?;
+        return export_chat(db, &chat, output, "", false).await;
+    }
+
+    let mut index = String::new();
+    for chat_id in chat_ids {
+        let chat = ChatExport::load(db, *chat_id, filter).await?;
+        let dir = format!("chats/chat_{}", chat_id);
+        export_chat(db, &chat, output, &dir, true).await?;
+
+        write!(
+            index,
+            r#"<a href="{}/messages.html">{}<span class="count">{} messages</span></a>"#,
+            dir,
+            escape_html(&chat.name),
+            chat.messages.len()
+        )?;
+    }
+
+    let page = render_page(
+        "Chats",
+        "Chats",
+        &format!(r#"<main class="chats">{}</main>"#, index),
+    );
+    tokio::fs::write(output.join("index.html"), page).await?;
+
+    Ok(())
+}
+
+async fn export_chat(
+    db: &Db,
+    chat: &ChatExport,
+    root: &Path,
+    dir: &str,
+    has_index: bool,
+) -> anyhow::Result<()> {
+    let chat_dir = root.join(dir);
+    tokio::fs::create_dir_all(&chat_dir).await?;
+
+    let names = chat.load_name_history(db).await?;
+    let posts = group_posts(chat.messages.clone());
+    let mut pages: Vec<&[Post]> = posts.chunks(PAGE_SIZE).collect();
+    // Filters may leave nothing, the chat still gets a page to link to
+    if pages.is_empty() {
+        pages.push(&[]);
+    }
+
+    // Replies may point at messages on other pages
+    let mut message_pages = HashMap::new();
+    for (page, posts) in pages.iter().enumerate() {
+        for post in posts.iter() {
+            for message in &post.messages {
+                message_pages.insert(message.id, page);
+            }
+        }
+    }
+    let messages: HashMap<i32, &entity::messages::Model> = chat
+        .messages
+        .iter()
+        .map(|message| (message.id, message))
+        .collect();
+
+    let context = PostContext {
+        chat,
+        names: &names,
+        messages: &messages,
+        message_pages: &message_pages,
+        root,
+        dir,
+    };
+
+    for (page, posts) in pages.iter().enumerate() {
+        let mut body = String::new();
+        let mut last_day = None;
+
+        for post in posts.iter() {
+            let date = parse_date(&post.first().date);
+            let day = date.map(|date| date.format("%-d %B %Y").to_string());
+            if day.is_some() && day != last_day {
+                write!(
+                    body,
+                    r#"<div class="day"><span>{}</span></div>"#,
+                    day.as_deref().unwrap_or_default()
+                )?;
+                last_day = day;
+            }
+
+            body.push_str(&render_post(&context, post, date).await?);
+        }
+
+        let navigation = render_navigation(page, pages.len(), has_index);
+        let content = format!(
+            r#"{}<main class="history">{}</main>{}"#,
+            navigation, body, navigation
+        );
+        let html = render_page(&chat.name, &chat.name, &content);
+        tokio::fs::write(chat_dir.join(get_page_name(page)), html).await?;
+    }
+
+    Ok(())
+}
+
+struct PostContext<'a> {
+    chat: &'a ChatExport,
+    names: &'a NameHistory,
+    messages: &'a HashMap<i32, &'a entity::messages::Model>,
+    message_pages: &'a HashMap<i32, usize>,
+    root: &'a Path,
+    dir: &'a str,
+}
+
+async fn render_post(
+    context: &PostContext<'_>,
+    post: &Post,
+    date: Option<DateTime<Utc>>,
+) -> anyhow::Result<String> {
+    let chat = context.chat;
+    let first = post.first();
+    let caption = post.caption();
+
+    let mut html = String::new();
+    write!(
+        html,
+        r#"<div class="message{}" id="message{}">"#,
+        if first.deleted_at.is_some() {
+            " deleted"
+        } else {
+            ""
+        },
+        first.id
+    )?;
+    // Every message of an album can be linked to
+    for message in post.messages.iter().skip(1) {
+        write!(html, r#"<a id="message{}"></a>"#, message.id)?;
+    }
+
+    let current_name = chat.sender_name(first);
+    let name = context
+        .names
+        .name_at(first.user_id, date)
+        .unwrap_or(&current_name);
+    write!(
+        html,
+        r#"<div class="from" title="{}">{}</div>"#,
+        escape_html(&current_name),
+        escape_html(name)
+    )?;
+
+    if let Some(forwarded_from) = first.forward_from_name.clone().or_else(|| {
+        first
+            .forward_from_id
+            .and_then(|id| chat.peer_name(id).map(str::to_string))
+    }) {
+        write!(
+            html,
+            r#"<div class="forwarded">Forwarded from {}</div>"#,
+            escape_html(&forwarded_from)
+        )?;
+    }
+
+    if let Some(reply_to) = first.reply_to_message_id {
+        let snippet = context
+            .messages
+            .get(&reply_to)
+            .map(|message| format!("{}: {}", chat.sender_name(message), get_snippet(message)))
+            .unwrap_or_else(|| "Message not archived".to_string());
+        match context.message_pages.get(&reply_to) {
+            Some(page) => write!(
+                html,
+                r##"<a class="reply" href="{}#message{}">{}</a>"##,
+                get_page_name(*page),
+                reply_to,
+                escape_html(&snippet)
+            )?,
+            None => write!(
+                html,
+                r#"<div class="reply">{}</div>"#,
+                escape_html(&snippet)
+            )?,
+        }
+    }
+
+    for message in &post.messages {
+        if message.has_binary_data && message.web_page.is_none() {
+            html.push_str(&render_media(context, message).await?);
+        }
+        if let Some(media_data) = media_data_from_json(message.media_data.as_deref()) {
+            html.push_str(&render_media_data(&media_data));
+        }
+    }
+
+    if !caption.text.is_empty() {
+        let entities = entities_from_json(caption.entities.as_deref());
+        write!(
+            html,
+            r#"<div class="text">{}</div>"#,
+            render_html(&caption.text, &entities)
+        )?;
+    }
+
+    if let Some(preview) = web_page_from_json(caption.web_page.as_deref()) {
+        html.push_str(r#"<div class="preview">"#);
+        if let Some(site_name) = &preview.site_name {
+            write!(
+                html,
+                r#"<div class="site">{}</div>"#,
+                escape_html(site_name)
+            )?;
+        }
+        let title = escape_html(preview.title.as_deref().unwrap_or(&preview.display_url));
+        if is_safe_url(&preview.url) {
+            write!(
+                html,
+                r#"<a href="{}">{}</a>"#,
+                escape_html(&preview.url),
+                title
+            )?;
+        } else {
+            html.push_str(&title);
+        }
+        if let Some(description) = &preview.description {
+            write!(html, "<div>{}</div>", escape_html(description))?;
+        }
+        if caption.has_binary_data {
+            if let Some(path) = export_media(context, caption).await? {
+                write!(
+                    html,
+                    r#"<img src="{}" loading="lazy" alt="">"#,
+                    escape_html(&path)
+                )?;
+            }
+        }
+        html.push_str("</div>");
+    }
+
+    let mut meta = vec![];
+    if let Some(post_author) = &first.post_author {
+        meta.push(escape_html(post_author));
+    }
+    if caption.edit_date.is_some() {
+        meta.push("edited".to_string());
+    }
+    if let Some(deleted_at) = &first.deleted_at {
+        meta.push(format!("deleted {}", escape_html(deleted_at)));
+    }
+    if let Some(date) = date {
+        meta.push(format!(
+            r#"<time datetime="{}" title="{}">{}</time>"#,
+            date.to_rfc3339(),
+            date.format("%Y-%m-%d %H:%M:%S UTC"),
+            date.format("%H:%M")
+        ));
+    }
+    write!(html, r#"<div class="meta">{}</div>"#, meta.join(" · "))?;
+
+    html.push_str("</div>");
+
+    Ok(html)
+}
+
+/// Exports the media file of a message, returning its path relative to the chat pages.
+async fn export_media(
+    context: &PostContext<'_>,
+    message: &entity::messages::Model,
+) -> anyhow::Result<Option<String>> {
+    let path = context
+        .chat
+        .export_media(message, context.root, context.dir)
+        .await?;
+
+    Ok(path.map(|path| strip_dir(path, context.dir)))
+}
+
+async fn render_media(
+    context: &PostContext<'_>,
+    message: &entity::messages::Model,
+) -> anyhow::Result<String> {
+    let media = context.chat.media(message);
+    let kind = media
+        .and_then(|media| media.kind.as_deref())
+        .unwrap_or("file");
+
+    let Some(path) = export_media(context, message).await? else {
+        return Ok(format!(
+            r#"<div class="media"><span class="missing">[{} not downloaded]</span></div>"#,
+            kind.replace('_', " ")
+        ));
+    };
+    let path = escape_html(&path);
+
+    let html = match kind {
+        "photo" | "sticker" => format!(r#"<img src="{}" loading="lazy" alt="{}">"#, path, kind),
+        "video" | "animation" | "round_video" => {
+            format!(
+                r#"<video src="{}" controls preload="metadata"></video>"#,
+                path
+            )
+        }
+        "voice" | "audio" => format!(r#"<audio src="{}" controls preload="none"></audio>"#, path),
+        _ => {
+            let file_name = media
+                .and_then(|media| media.file_name.as_deref())
+                .unwrap_or(kind);
+            let size = media
+                .and_then(|media| media.size)
+                .map(|size| format!(" ({} kB)", size / 1024))
+                .unwrap_or_default();
+            format!(
+                r#"<a href="{}">{}</a>{}"#,
+                path,
+                escape_html(file_name),
+                size
+            )
+        }
+    };
+
+    Ok(format!(r#"<div class="media">{}</div>"#, html))
+}
+
+fn render_media_data(media_data: &MediaData) -> String {
+    let location = |latitude: f64, longitude: f64| {
+        format!(
+            r#" <a href="https://www.openstreetmap.org/?mlat={0}&amp;mlon={1}#map=16/{0}/{1}">map</a>"#,
+            latitude, longitude
+        )
+    };
+
+    let html = match media_data {
+        MediaData::Poll {
+            question, options, ..
+        } => {
+            let options: String = options
+                .iter()
+                .map(|option| {
+                    format!(
+                        "<li>{}{}</li>",
+                        escape_html(&option.text),
+                        option
+                            .voters
+                            .map(|voters| format!(" — {} votes", voters))
+                            .unwrap_or_default()
+                    )
+                })
+                .collect();
+            format!("<b>{}</b><ul>{}</ul>", escape_html(question), options)
+        }
+        MediaData::Geo {
+            latitude,
+            longitude,
+            ..
+        }
+        | MediaData::LiveLocation {
+            latitude,
+            longitude,
+            ..
+        } => format!(
+            "{}{}",
+            escape_html(&media_data.summary()),
+            location(*latitude, *longitude)
+        ),
+        MediaData::Venue {
+            latitude: Some(latitude),
+            longitude: Some(longitude),
+            ..
+        } => format!(
+            "{}{}",
+            escape_html(&media_data.summary()),
+            location(*latitude, *longitude)
+        ),
+        _ => escape_html(&media_data.summary()),
+    };
+
+    format!(r#"<div class="media">{}</div>"#, html)
+}
+
+fn render_navigation(page: usize, pages: usize, has_index: bool) -> String {
+    let mut links = vec![];
+    if has_index {
+        links.push(r#"<a href="../../index.html">All chats</a>"#.to_string());
+    }
+    if page > 0 {
+        links.push(format!(
+            r#"<a href="{}">« Previous</a>"#,
+            get_page_name(page - 1)
+        ));
+    }
+    if pages > 1 {
+        links.push(format!("Page {} of {}", page + 1, pages));
+    }
+    if page + 1 < pages {
+        links.push(format!(
+            r#"<a href="{}">Next »</a>"#,
+            get_page_name(page + 1)
+        ));
+    }
+
+    format!("<nav>{}</nav>", links.join(" · "))
+}
+
+fn render_page(title: &str, heading: &str, content: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{}</title>
+<style>{}</style>
+</head>
+<body>
+<header><h1>{}</h1></header>
+{}
+</body>
+</html>
+"#,
+        escape_html(title),
+        STYLE,
+        escape_html(heading),
+        content
+    )
+}
+
+/// Page names follow Telegram Desktop: `messages.html`, `messages2.html`, ...
+fn get_page_name(page: usize) -> String {
+    match page {
+        0 => "messages.html".to_string(),
+        _ => format!("messages{}.html", page + 1),
+    }
+}
+
+fn get_snippet(message: &entity::messages::Model) -> String {
+    let text = if message.text.is_empty() {
+        media_data_from_json(message.media_data.as_deref())
+            .map(|media_data| media_data.summary())
+            .unwrap_or_else(|| {
+                if message.has_binary_data {
+                    "[media]".to_string()
+                } else {
+                    String::new()
+                }
+            })
+    } else {
+        message.text.clone()
+    };
+
+    match text.char_indices().nth(80) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text,
+    }
+}
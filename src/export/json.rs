@@ -0,0 +1,401 @@
+//! Export in the `result.json` format of Telegram Desktop.
+//!
+//! A single chat is written as one chat object with its media next to `result.json`, several
+//! chats are listed under `chats.list` with their media in `chats/chat_<id>/`.
+
+use std::path::Path;
+
+use serde_json::{json, Map, Value};
+
+use super::{parse_date, ChatExport, ExportFilter};
+use crate::chat_kind::ChatKind;
+use crate::db::Db;
+use crate::formatting::{entities_from_json, EntityKind, TextEntity};
+use crate::media_data::{media_data_from_json, MediaData};
+
+/// What Telegram Desktop writes in place of media that was not exported.
+const FILE_NOT_INCLUDED: &str = "(File not included. Change data exporting settings to download.)";
+
+pub async fn export(
+    db: &Db,
+    chat_ids: &[i64],
+    filter: &ExportFilter,
+    output: &Path,
+) -> anyhow::Result<()> {
+    let result = if let [chat_id] = chat_ids {
+        let chat = ChatExport::load(db, *chat_id, filter).await?;
+        chat_to_json(&chat, output, "").await?
+    } else {
+        let mut list = vec![];
+        for chat_id in chat_ids {
+            let chat = ChatExport::load(db, *chat_id, filter).await?;
+            let dir = format!("chats/chat_{}", chat_id);
+            list.push(chat_to_json(&chat, output, &dir).await?);
+        }
+
+        json!({
+            "about": "Here is the data you requested. Exported by teledump.",
+            "chats": {
+                "about": "This page lists all chats from this export.",
+                "list": list,
+            },
+        })
+    };
+
+    tokio::fs::write(
+        output.join("result.json"),
+        serde_json::to_vec_pretty(&result)?,
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn chat_to_json(chat: &ChatExport, root: &Path, dir: &str) -> anyhow::Result<Value> {
+    let mut messages = Vec::with_capacity(chat.messages.len());
+    for message in &chat.messages {
+        messages.push(message_to_json(chat, message, root, dir).await?);
+    }
+
+    Ok(json!({
+        "name": chat.name,
+        "type": get_chat_type(chat),
+        "id": chat.id,
+        "messages": messages,
+    }))
+}
+
+fn get_chat_type(chat: &ChatExport) -> &'static str {
+    let public = chat.username.is_some();
+    match chat.kind {
+        Some(ChatKind::User) if chat.is_bot => "bot_chat",
+        Some(ChatKind::User) | None => "personal_chat",
+        Some(ChatKind::Group) => "private_group",
+        Some(ChatKind::Supergroup) if public => "public_supergroup",
+        Some(ChatKind::Supergroup) => "private_supergroup",
+        Some(ChatKind::Channel) if public => "public_channel",
+        Some(ChatKind::Channel) => "private_channel",
+    }
+}
+
+async fn message_to_json(
+    chat: &ChatExport,
+    message: &entity::messages::Model,
+    root: &Path,
+    dir: &str,
+) -> anyhow::Result<Value> {
+    let mut object = Map::new();
+
+    object.insert("id".into(), message.id.into());
+    object.insert("type".into(), "message".into());
+    insert_date(&mut object, "date", &message.date);
+    if let Some(edit_date) = &message.edit_date {
+        insert_date(&mut object, "edited", edit_date);
+    }
+
+    object.insert("from".into(), chat.sender_name(message).into());
+    let from_kind = if chat.is_chat_peer(message.user_id) {
+        "channel"
+    } else {
+        "user"
+    };
+    object.insert(
+        "from_id".into(),
+        format!("{}{}", from_kind, message.user_id).into(),
+    );
+    if let Some(post_author) = &message.post_author {
+        object.insert("author".into(), post_author.clone().into());
+    }
+
+    if let Some(reply_to_message_id) = message.reply_to_message_id {
+        object.insert("reply_to_message_id".into(), reply_to_message_id.into());
+    }
+    let forwarded_from = message.forward_from_name.clone().or_else(|| {
+        message
+            .forward_from_id
+            .and_then(|id| chat.peer_name(id).map(str::to_string))
+    });
+    if let Some(forwarded_from) = forwarded_from {
+        object.insert("forwarded_from".into(), forwarded_from.into());
+    }
+
+    // Link preview photos are not exported, Telegram Desktop has no place for them
+    if message.has_binary_data && message.web_page.is_none() {
+        insert_media(&mut object, chat, message, root, dir).await?;
+    }
+    if let Some(media_data) = media_data_from_json(message.media_data.as_deref()) {
+        insert_media_data(&mut object, media_data);
+    }
+
+    let text_entities = get_text_entities(
+        &message.text,
+        &entities_from_json(message.entities.as_deref()),
+    );
+    let text = if text_entities.iter().all(|entity| entity["type"] == "plain") {
+        Value::from(message.text.clone())
+    } else {
+        Value::from(
+            text_entities
+                .iter()
+                .map(|entity| match entity["type"].as_str() {
+                    Some("plain") => entity["text"].clone(),
+                    _ => entity.clone(),
+                })
+                .collect::<Vec<_>>(),
+        )
+    };
+    object.insert("text".into(), text);
+    object.insert("text_entities".into(), text_entities.into());
+
+    Ok(Value::Object(object))
+}
+
+fn insert_date(object: &mut Map<String, Value>, key: &str, date: &str) {
+    let Some(date) = parse_date(date) else {
+        return;
+    };
+
+    object.insert(
+        key.into(),
+        date.format("%Y-%m-%dT%H:%M:%S").to_string().into(),
+    );
+    object.insert(
+        format!("{}_unixtime", key),
+        date.timestamp().to_string().into(),
+    );
+}
+
+async fn insert_media(
+    object: &mut Map<String, Value>,
+    chat: &ChatExport,
+    message: &entity::messages::Model,
+    root: &Path,
+    dir: &str,
+) -> anyhow::Result<()> {
+    let path = chat
+        .export_media(message, root, dir)
+        .await?
+        .unwrap_or_else(|| FILE_NOT_INCLUDED.to_string());
+    let media = chat.media(message);
+    let kind = media.and_then(|media| media.kind.as_deref());
+
+    if kind == Some("photo") {
+        object.insert("photo".into(), path.into());
+    } else {
+        object.insert("file".into(), path.into());
+        if let Some(file_name) = media.and_then(|media| media.file_name.clone()) {
+            object.insert("file_name".into(), file_name.into());
+        }
+
+        let media_type = match kind {
+            Some("sticker") => Some("sticker"),
+            Some("animation") => Some("animation"),
+            Some("video") => Some("video_file"),
+            Some("round_video") => Some("video_message"),
+            Some("voice") => Some("voice_message"),
+            Some("audio") => Some("audio_file"),
+            _ => None,
+        };
+        if let Some(media_type) = media_type {
+            object.insert("media_type".into(), media_type.into());
+        }
+    }
+
+    let Some(media) = media else {
+        return Ok(());
+    };
+
+    if kind != Some("photo") {
+        if let Some(mime_type) = &media.mime_type {
+            object.insert("mime_type".into(), mime_type.clone().into());
+        }
+    }
+    if let Some(size) = media.size {
+        object.insert("file_size".into(), size.into());
+    }
+    if let Some(duration) = media.duration {
+        object.insert("duration_seconds".into(), duration.into());
+    }
+    if let (Some(width), Some(height)) = (media.width, media.height) {
+        object.insert("width".into(), width.into());
+        object.insert("height".into(), height.into());
+    }
+
+    Ok(())
+}
+
+fn insert_media_data(object: &mut Map<String, Value>, media_data: MediaData) {
+    match media_data {
+        MediaData::Poll {
+            question,
+            options,
+            closed,
+            total_voters,
+            ..
+        } => {
+            let answers: Vec<Value> = options
+                .into_iter()
+                .map(|option| {
+                    json!({
+                        "text": option.text,
+                        "voters": option.voters.unwrap_or_default(),
+                        "chosen": option.chosen,
+                    })
+                })
+                .collect();
+
+            object.insert(
+                "poll".into(),
+                json!({
+                    "question": question,
+                    "closed": closed,
+                    "total_voters": total_voters.unwrap_or_default(),
+                    "answers": answers,
+                }),
+            );
+        }
+        MediaData::Geo {
+            latitude,
+            longitude,
+            ..
+        } => {
+            object.insert(
+                "location_information".into(),
+                json!({ "latitude": latitude, "longitude": longitude }),
+            );
+        }
+        MediaData::LiveLocation {
+            latitude,
+            longitude,
+            period,
+            ..
+        } => {
+            object.insert(
+                "location_information".into(),
+                json!({ "latitude": latitude, "longitude": longitude }),
+            );
+            object.insert("live_location_period_seconds".into(), period.into());
+        }
+        MediaData::Venue {
+            latitude,
+            longitude,
+            title,
+            address,
+            ..
+        } => {
+            object.insert("place_name".into(), title.into());
+            object.insert("address".into(), address.into());
+            if let (Some(latitude), Some(longitude)) = (latitude, longitude) {
+                object.insert(
+                    "location_information".into(),
+                    json!({ "latitude": latitude, "longitude": longitude }),
+                );
+            }
+        }
+        MediaData::Dice { emoticon, value } => {
+            object.insert(
+                "dice".into(),
+                json!({ "emoticon": emoticon, "value": value }),
+            );
+        }
+        MediaData::Contact {
+            phone_number,
+            first_name,
+            last_name,
+            ..
+        } => {
+            object.insert(
+                "contact_information".into(),
+                json!({
+                    "first_name": first_name,
+                    "last_name": last_name,
+                    "phone_number": phone_number,
+                }),
+            );
+        }
+        MediaData::Game {
+            title, description, ..
+        } => {
+            object.insert("game_title".into(), title.into());
+            object.insert("game_description".into(), description.into());
+        }
+    }
+}
+
+/// Splits the text into plain and formatted parts the way Telegram Desktop does. Nested
+/// entities are flattened into the outermost one.
+fn get_text_entities(text: &str, entities: &[TextEntity]) -> Vec<Value> {
+    let units: Vec<u16> = text.encode_utf16().collect();
+    let slice = |start: usize, end: usize| String::from_utf16_lossy(&units[start..end]);
+
+    let mut entities: Vec<&TextEntity> = entities
+        .iter()
+        .filter(|entity| entity.offset >= 0 && entity.length > 0)
+        .filter(|entity| (entity.offset + entity.length) as usize <= units.len())
+        .collect();
+    entities.sort_by_key(|entity| (entity.offset, -entity.length));
+
+    let mut parts = vec![];
+    let mut position = 0;
+    for entity in entities {
+        let start = entity.offset as usize;
+        let end = start + entity.length as usize;
+        if start < position {
+            continue;
+        }
+
+        if start > position {
+            parts.push(json!({ "type": "plain", "text": slice(position, start) }));
+        }
+
+        let mut part = Map::new();
+        part.insert("type".into(), get_entity_type(&entity.kind).into());
+        part.insert("text".into(), slice(start, end).into());
+        if let Some(url) = &entity.url {
+            part.insert("href".into(), url.clone().into());
+        }
+        if let Some(user_id) = entity.user_id {
+            part.insert("user_id".into(), user_id.into());
+        }
+        if entity.kind == EntityKind::Pre {
+            part.insert(
+                "language".into(),
+                entity.language.clone().unwrap_or_default().into(),
+            );
+        }
+        parts.push(Value::Object(part));
+
+        position = end;
+    }
+
+    if position < units.len() {
+        parts.push(json!({ "type": "plain", "text": slice(position, units.len()) }));
+    }
+
+    parts
+}
+
+fn get_entity_type(kind: &EntityKind) -> &'static str {
+    match kind {
+        EntityKind::Bold => "bold",
+        EntityKind::Italic => "italic",
+        EntityKind::Underline => "underline",
+        EntityKind::Strike => "strikethrough",
+        EntityKind::Spoiler => "spoiler",
+        EntityKind::Code => "code",
+        EntityKind::Pre => "pre",
+        EntityKind::TextUrl => "text_link",
+        EntityKind::Url => "link",
+        EntityKind::Email => "email",
+        EntityKind::Phone => "phone",
+        EntityKind::Mention => "mention",
+        EntityKind::MentionName => "mention_name",
+        EntityKind::Hashtag => "hashtag",
+        EntityKind::Cashtag => "cashtag",
+        EntityKind::BotCommand => "bot_command",
+        EntityKind::BankCard => "bank_card",
+        EntityKind::Blockquote => "blockquote",
+        EntityKind::CustomEmoji => "custom_emoji",
+        EntityKind::Unknown => "unknown",
+    }
+}
@@ -0,0 +1,334 @@
+//! Exports of the archive into formats other tools and people can read.
+
+mod html;
+mod json;
+mod transcript;
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::path::Path;
+
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use clap::ValueEnum;
+use log::{info, warn};
+use tokio::fs::{copy, create_dir_all, hard_link};
+
+use crate::chat_kind::ChatKind;
+use crate::db::Db;
+use transcript::TranscriptFormat;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// `result.json` as written by Telegram Desktop
+    Json,
+    /// Paginated static pages, readable in any browser
+    Html,
+    /// Plain text transcript, one line per message
+    Txt,
+    /// Markdown transcript, one line per message
+    #[value(alias = "md")]
+    Markdown,
+}
+
+/// Which messages of a chat to export, everything by default.
+#[derive(Clone, Debug, Default)]
+pub struct ExportFilter {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// Sender ids, any sender if empty
+    pub senders: Vec<i64>,
+}
+
+impl ExportFilter {
+    fn matches(&self, message: &entity::messages::Model) -> bool {
+        if !self.senders.is_empty() && !self.senders.contains(&message.user_id) {
+            return false;
+        }
+
+        let Some(date) = parse_date(&message.date) else {
+            return self.since.is_none() && self.until.is_none();
+        };
+        self.since.map_or(true, |since| date >= since)
+            && self.until.map_or(true, |until| date < until)
+    }
+}
+
+/// Exports the given chats, or every archived chat if `chat_ids` is empty, into `output`.
+pub async fn export(
+    db: &Db,
+    chat_ids: &[i64],
+    format: ExportFormat,
+    filter: &ExportFilter,
+    output: &Path,
+) -> anyhow::Result<()> {
+    let chat_ids = db.get_archived_chat_ids(chat_ids).await?;
+    if chat_ids.is_empty() {
+        anyhow::bail!("There are no archived messages to export");
+    }
+
+    create_dir_all(output).await?;
+
+    match format {
+        ExportFormat::Json => json::export(db, &chat_ids, filter, output).await?,
+        ExportFormat::Html => html::export(db, &chat_ids, filter, output).await?,
+        ExportFormat::Txt => {
+            transcript::export(db, &chat_ids, filter, output, TranscriptFormat::Text).await?
+        }
+        ExportFormat::Markdown => {
+            transcript::export(db, &chat_ids, filter, output, TranscriptFormat::Markdown).await?
+        }
+    }
+
+    info!(
+        "Exported {} chat(s) into {}",
+        chat_ids.len(),
+        output.display()
+    );
+
+    Ok(())
+}
+
+/// A chat with its messages and everything needed to render them.
+pub struct ChatExport {
+    pub id: i64,
+    pub name: String,
+    pub kind: Option<ChatKind>,
+    pub username: Option<String>,
+    pub is_bot: bool,
+    pub messages: Vec<entity::messages::Model>,
+    media: HashMap<i32, entity::media::Model>,
+    names: HashMap<i64, String>,
+    /// Peers that are chats rather than users, e.g. channels posting on their own
+    chat_peers: HashSet<i64>,
+}
+
+impl ChatExport {
+    pub async fn load(db: &Db, chat_id: i64, filter: &ExportFilter) -> anyhow::Result<Self> {
+        let mut messages = db.get_messages_by_chat(chat_id).await?;
+        messages.retain(|message| filter.matches(message));
+
+        let media_ids: Vec<i32> = messages
+            .iter()
+            .filter_map(|message| message.media_id)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        let media = db
+            .get_media_by_ids(&media_ids)
+            .await?
+            .into_iter()
+            .map(|media| (media.id, media))
+            .collect();
+
+        let peer_ids: Vec<i64> = messages
+            .iter()
+            .flat_map(|message| [Some(message.user_id), message.forward_from_id])
+            .flatten()
+            .chain([chat_id])
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+
+        let mut names = HashMap::new();
+        let mut is_bot = false;
+        for user in db.get_users_by_ids(&peer_ids).await? {
+            if user.id == chat_id {
+                is_bot = user.is_bot;
+            }
+            let name = match &user.last_name {
+                Some(last_name) if !last_name.is_empty() => {
+                    format!("{} {}", user.first_name, last_name)
+                }
+                _ => user.first_name.clone(),
+            };
+            names.insert(user.id, name);
+        }
+
+        let mut chat = None;
+        let mut chat_peers = HashSet::new();
+        for peer in db.get_chats_by_ids(&peer_ids).await? {
+            if peer.kind != ChatKind::User.as_str() {
+                chat_peers.insert(peer.id);
+            }
+            names.entry(peer.id).or_insert_with(|| peer.title.clone());
+            if peer.id == chat_id {
+                chat = Some(peer);
+            }
+        }
+
+        let kind = match &chat {
+            Some(chat) => chat.kind.parse().ok(),
+            None => messages
+                .first()
+                .and_then(|message| message.chat_kind.parse().ok()),
+        };
+
+        Ok(ChatExport {
+            id: chat_id,
+            name: names
+                .get(&chat_id)
+                .cloned()
+                .unwrap_or_else(|| format!("Chat {}", chat_id)),
+            kind,
+            username: chat.and_then(|chat| chat.username),
+            is_bot,
+            messages,
+            media,
+            names,
+            chat_peers,
+        })
+    }
+
+    pub fn peer_name(&self, id: i64) -> Option<&str> {
+        self.names.get(&id).map(String::as_str)
+    }
+
+    pub fn is_chat_peer(&self, id: i64) -> bool {
+        self.chat_peers.contains(&id)
+    }
+
+    pub fn sender_name(&self, message: &entity::messages::Model) -> String {
+        self.peer_name(message.user_id)
+            .map(str::to_string)
+            .unwrap_or_else(|| message.user_id.to_string())
+    }
+
+    pub fn media(&self, message: &entity::messages::Model) -> Option<&entity::media::Model> {
+        message
+            .media_id
+            .and_then(|media_id| self.media.get(&media_id))
+    }
+
+    /// Puts the downloaded media file of a message under `root/dir`, grouped by kind, and
+    /// returns its path relative to `root`. Returns `None` if the file was never downloaded.
+    pub async fn export_media(
+        &self,
+        message: &entity::messages::Model,
+        root: &Path,
+        dir: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let media = self.media(message);
+        let source = match media {
+            Some(media) => media.path.as_deref(),
+            None => message.binary_data_path.as_deref(),
+        };
+        let Some(source) = source.filter(|source| Path::new(source).exists()) else {
+            return Ok(None);
+        };
+
+        let kind = media.and_then(|media| media.kind.as_deref());
+        let extension = Path::new(source)
+            .extension()
+            .and_then(OsStr::to_str)
+            .map(|extension| format!(".{}", extension))
+            .unwrap_or_default();
+        let file_name = match media.and_then(|media| media.file_name.as_deref()) {
+            Some(file_name) => format!("{}_{}", message.id, file_name.replace(['/', '\\'], "_")),
+            None => format!("{}_{}{}", kind.unwrap_or("file"), message.id, extension),
+        };
+
+        let relative = [dir, get_media_folder(kind), &file_name]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("/");
+        let destination = root.join(&relative);
+        if destination.exists() {
+            return Ok(Some(relative));
+        }
+
+        if let Some(parent) = destination.parent() {
+            create_dir_all(parent).await?;
+        }
+        // Blobs are never modified in place, so sharing them is safe and saves space
+        if hard_link(source, &destination).await.is_err() {
+            if let Err(e) = copy(source, &destination).await {
+                warn!("Failed to export {}: {}", source, e);
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(relative))
+    }
+
+    /// Names of the senders over time, taken from their profile revisions.
+    async fn load_name_history(&self, db: &Db) -> anyhow::Result<NameHistory> {
+        let mut names = HashMap::new();
+
+        let user_ids: HashSet<i64> = self
+            .messages
+            .iter()
+            .map(|message| message.user_id)
+            .filter(|user_id| !self.is_chat_peer(*user_id))
+            .collect();
+
+        for user_id in user_ids {
+            let revisions = db.get_user_revisions(user_id).await?;
+            let history: Vec<(DateTime<Utc>, String)> = revisions
+                .into_iter()
+                .filter_map(|revision| {
+                    let seen_at = parse_date(&revision.seen_at)?;
+                    let name = match revision.last_name {
+                        Some(last_name) if !last_name.is_empty() => {
+                            format!("{} {}", revision.first_name, last_name)
+                        }
+                        _ => revision.first_name,
+                    };
+                    Some((seen_at, name))
+                })
+                .collect();
+
+            if !history.is_empty() {
+                names.insert(user_id, history);
+            }
+        }
+
+        Ok(NameHistory { names })
+    }
+}
+
+/// Names users had over time, so messages show the name their sender had back then.
+struct NameHistory {
+    names: HashMap<i64, Vec<(DateTime<Utc>, String)>>,
+}
+
+impl NameHistory {
+    fn name_at(&self, user_id: i64, date: Option<DateTime<Utc>>) -> Option<&String> {
+        let names = self.names.get(&user_id)?;
+        let date = date?;
+
+        names
+            .iter()
+            .take_while(|(seen_at, _)| *seen_at <= date)
+            .last()
+            .or(names.first())
+            .map(|(_, name)| name)
+    }
+}
+
+/// Folder Telegram Desktop puts media of the given kind in.
+fn get_media_folder(kind: Option<&str>) -> &'static str {
+    match kind {
+        Some("photo") => "photos",
+        Some("video") | Some("animation") => "video_files",
+        Some("round_video") => "round_video_messages",
+        Some("voice") => "voice_messages",
+        Some("sticker") => "stickers",
+        _ => "files",
+    }
+}
+
+/// Makes a path relative to `root`, as returned by `export_media`, relative to `dir` instead.
+pub fn strip_dir(path: String, dir: &str) -> String {
+    match path.strip_prefix(dir) {
+        Some(path) if !dir.is_empty() => path.trim_start_matches('/').to_string(),
+        _ => path,
+    }
+}
+
+/// Parses dates as they are stored in the database.
+pub fn parse_date(date: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(date.trim_end_matches(" UTC"), "%Y-%m-%d %H:%M:%S%.f")
+        .ok()
+        .map(|date| Utc.from_utc_datetime(&date))
+}
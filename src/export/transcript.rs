@@ -0,0 +1,150 @@
+//! Plain text and Markdown transcripts with one line per message, easy to read and grep.
+
+use std::path::Path;
+
+use chrono::SecondsFormat;
+
+use super::{parse_date, strip_dir, ChatExport, ExportFilter};
+use crate::db::Db;
+use crate::formatting::{
+    entities_from_json, escape_link_target, escape_markdown, is_safe_url, render_markdown,
+};
+use crate::media_data::media_data_from_json;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TranscriptFormat {
+    Text,
+    Markdown,
+}
+
+impl TranscriptFormat {
+    fn file_name(&self) -> &'static str {
+        match self {
+            TranscriptFormat::Text => "messages.txt",
+            TranscriptFormat::Markdown => "messages.md",
+        }
+    }
+}
+
+pub async fn export(
+    db: &Db,
+    chat_ids: &[i64],
+    filter: &ExportFilter,
+    output: &Path,
+    format: TranscriptFormat,
+) -> anyhow::Result<()> {
+    for chat_id in chat_ids {
+        let chat = ChatExport::load(db, *chat_id, filter).await?;
+        let dir = match chat_ids {
+            [_] => String::new(),
+            _ => format!("chats/chat_{}", chat_id),
+        };
+
+        let mut transcript = String::new();
+        if format == TranscriptFormat::Markdown {
+            transcript.push_str(&format!("# {}\n\n", escape_markdown(&chat.name)));
+        }
+        for message in &chat.messages {
+            transcript.push_str(&render_line(&chat, message, output, &dir, format).await?);
+            transcript.push('\n');
+        }
+
+        let chat_dir = output.join(&dir);
+        tokio::fs::create_dir_all(&chat_dir).await?;
+        tokio::fs::write(chat_dir.join(format.file_name()), transcript).await?;
+    }
+
+    Ok(())
+}
+
+async fn render_line(
+    chat: &ChatExport,
+    message: &entity::messages::Model,
+    root: &Path,
+    dir: &str,
+    format: TranscriptFormat,
+) -> anyhow::Result<String> {
+    let markdown = format == TranscriptFormat::Markdown;
+    let date = parse_date(&message.date)
+        .map(|date| date.to_rfc3339_opts(SecondsFormat::Secs, true))
+        .unwrap_or_else(|| message.date.clone());
+    let name = chat.sender_name(message);
+
+    let mut parts = vec![];
+    if let Some(forwarded_from) = message.forward_from_name.clone().or_else(|| {
+        message
+            .forward_from_id
+            .and_then(|id| chat.peer_name(id).map(str::to_string))
+    }) {
+        parts.push(format!(
+            "(forwarded from {})",
+            escape(&forwarded_from, markdown)
+        ));
+    }
+    if let Some(reply_to) = message.reply_to_message_id {
+        parts.push(format!("(reply to #{})", reply_to));
+    }
+
+    if markdown {
+        let entities = entities_from_json(message.entities.as_deref());
+        parts.push(render_markdown(&message.text, &entities).replace('\n', "<br>"));
+    } else {
+        parts.push(message.text.replace('\n', "\\n"));
+    }
+    if let Some(media_data) = media_data_from_json(message.media_data.as_deref()) {
+        parts.push(escape(&media_data.summary(), markdown));
+    }
+
+    if message.has_binary_data && message.web_page.is_none() {
+        let kind = chat
+            .media(message)
+            .and_then(|media| media.kind.clone())
+            .unwrap_or_else(|| "file".to_string());
+        let path = chat
+            .export_media(message, root, dir)
+            .await?
+            .map(|path| strip_dir(path, dir));
+
+        parts.push(match (path, markdown) {
+            (Some(path), true) if is_linkable_path(&path) => {
+                format!("[{}](<{}>)", kind, escape_link_target(&path))
+            }
+            (Some(path), true) => escape_markdown(&format!("[{}: {}]", kind, path)),
+            (Some(path), false) => format!("[{}: {}]", kind, path),
+            (None, _) => escape(&format!("[{} not downloaded]", kind), markdown),
+        });
+    }
+
+    if message.edit_date.is_some() {
+        parts.push("(edited)".to_string());
+    }
+    if message.deleted_at.is_some() {
+        parts.push("(deleted)".to_string());
+    }
+
+    let content = parts
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    Ok(if markdown {
+        format!("- `{}` **{}**: {}", date, escape_markdown(&name), content)
+    } else {
+        format!("[{}] {}: {}", date, name, content)
+    })
+}
+
+/// Whether a media path can be used as a link target, a colon in its first segment would
+/// make it a URL with a scheme like `javascript:`.
+fn is_linkable_path(path: &str) -> bool {
+    is_safe_url(path) || !path.split('/').next().unwrap_or_default().contains(':')
+}
+
+fn escape(text: &str, markdown: bool) -> String {
+    if markdown {
+        escape_markdown(text)
+    } else {
+        text.to_string()
+    }
+}
@@ -0,0 +1,497 @@
+//! Message formatting entities and their rendering back into HTML or Markdown.
+//!
+//! Entity offsets and lengths are counted in UTF-16 code units, same as Telegram does.
+
+use clap::ValueEnum;
+use grammers_tl_types as tl;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntityKind {
+    Bold,
+    Italic,
+    Underline,
+    Strike,
+    Spoiler,
+    Code,
+    Pre,
+    TextUrl,
+    Url,
+    Email,
+    Phone,
+    Mention,
+    MentionName,
+    Hashtag,
+    Cashtag,
+    BotCommand,
+    BankCard,
+    Blockquote,
+    CustomEmoji,
+    Unknown,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TextEntity {
+    pub kind: EntityKind,
+    pub offset: i32,
+    pub length: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+impl TextEntity {
+    fn new(kind: EntityKind, offset: i32, length: i32) -> Self {
+        TextEntity {
+            kind,
+            offset,
+            length,
+            url: None,
+            user_id: None,
+            language: None,
+        }
+    }
+}
+
+impl From<&tl::enums::MessageEntity> for TextEntity {
+    fn from(entity: &tl::enums::MessageEntity) -> Self {
+        use tl::enums::MessageEntity as E;
+
+        match entity {
+            E::Bold(e) => TextEntity::new(EntityKind::Bold, e.offset, e.length),
+            E::Italic(e) => TextEntity::new(EntityKind::Italic, e.offset, e.length),
+            E::Underline(e) => TextEntity::new(EntityKind::Underline, e.offset, e.length),
+            E::Strike(e) => TextEntity::new(EntityKind::Strike, e.offset, e.length),
+            E::Spoiler(e) => TextEntity::new(EntityKind::Spoiler, e.offset, e.length),
+            E::Code(e) => TextEntity::new(EntityKind::Code, e.offset, e.length),
+            E::Pre(e) => TextEntity {
+                language: Some(e.language.clone()).filter(|language| !language.is_empty()),
+                ..TextEntity::new(EntityKind::Pre, e.offset, e.length)
+            },
+            E::TextUrl(e) => TextEntity {
+                url: Some(e.url.clone()),
+                ..TextEntity::new(EntityKind::TextUrl, e.offset, e.length)
+            },
+            E::Url(e) => TextEntity::new(EntityKind::Url, e.offset, e.length),
+            E::Email(e) => TextEntity::new(EntityKind::Email, e.offset, e.length),
+            E::Phone(e) => TextEntity::new(EntityKind::Phone, e.offset, e.length),
+            E::Mention(e) => TextEntity::new(EntityKind::Mention, e.offset, e.length),
+            E::MentionName(e) => TextEntity {
+                user_id: Some(e.user_id),
+                ..TextEntity::new(EntityKind::MentionName, e.offset, e.length)
+            },
+            E::Hashtag(e) => TextEntity::new(EntityKind::Hashtag, e.offset, e.length),
+            E::Cashtag(e) => TextEntity::new(EntityKind::Cashtag, e.offset, e.length),
+            E::BotCommand(e) => TextEntity::new(EntityKind::BotCommand, e.offset, e.length),
+            E::BankCard(e) => TextEntity::new(EntityKind::BankCard, e.offset, e.length),
+            E::Blockquote(e) => TextEntity::new(EntityKind::Blockquote, e.offset, e.length),
+            E::CustomEmoji(e) => TextEntity::new(EntityKind::CustomEmoji, e.offset, e.length),
+            E::Unknown(e) => TextEntity::new(EntityKind::Unknown, e.offset, e.length),
+            E::InputMessageEntityMentionName(e) => {
+                TextEntity::new(EntityKind::MentionName, e.offset, e.length)
+            }
+        }
+    }
+}
+
+pub fn entities_to_json(entities: &[tl::enums::MessageEntity]) -> Option<String> {
+    if entities.is_empty() {
+        return None;
+    }
+
+    let entities: Vec<TextEntity> = entities.iter().map(TextEntity::from).collect();
+    serde_json::to_string(&entities).ok()
+}
+
+pub fn entities_from_json(entities: Option<&str>) -> Vec<TextEntity> {
+    entities
+        .and_then(|entities| serde_json::from_str(entities).ok())
+        .unwrap_or_default()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Plain,
+    Html,
+    Markdown,
+}
+
+pub fn render(text: &str, entities: &[TextEntity], format: Format) -> String {
+    match format {
+        Format::Plain => text.to_string(),
+        Format::Html => render_html(text, entities),
+        Format::Markdown => render_markdown(text, entities),
+    }
+}
+
+pub fn render_html(text: &str, entities: &[TextEntity]) -> String {
+    render_with(text, entities, escape_html, false, |entity, content| {
+        let (open, close) = match entity.kind {
+            EntityKind::Bold => ("<b>".to_string(), "</b>"),
+            EntityKind::Italic => ("<i>".to_string(), "</i>"),
+            EntityKind::Underline => ("<u>".to_string(), "</u>"),
+            EntityKind::Strike => ("<s>".to_string(), "</s>"),
+            EntityKind::Spoiler => ("<span class=\"spoiler\">".to_string(), "</span>"),
+            EntityKind::Code => ("<code>".to_string(), "</code>"),
+            EntityKind::Pre => match &entity.language {
+                Some(language) => (
+                    format!("<pre><code class=\"language-{}\">", escape_html(language)),
+                    "</code></pre>",
+                ),
+                None => ("<pre>".to_string(), "</pre>"),
+            },
+            EntityKind::TextUrl => match entity.url.as_deref().filter(|url| is_safe_url(url)) {
+                Some(url) => (format!("<a href=\"{}\">", escape_html(url)), "</a>"),
+                None => (String::new(), ""),
+            },
+            EntityKind::Url => match url_entity_target(content) {
+                Some(url) => (format!("<a href=\"{}\">", escape_html(&url)), "</a>"),
+                None => (String::new(), ""),
+            },
+            EntityKind::Email => (
+                format!("<a href=\"mailto:{}\">", escape_html(content)),
+                "</a>",
+            ),
+            EntityKind::Phone => (format!("<a href=\"tel:{}\">", escape_html(content)), "</a>"),
+            EntityKind::Mention => (
+                format!(
+                    "<a href=\"https://t.me/{}\">",
+                    escape_html(content.trim_start_matches('@'))
+                ),
+                "</a>",
+            ),
+            EntityKind::MentionName => match entity.user_id {
+                Some(user_id) => (format!("<a href=\"tg://user?id={}\">", user_id), "</a>"),
+                None => (String::new(), ""),
+            },
+            EntityKind::Blockquote => ("<blockquote>".to_string(), "</blockquote>"),
+            _ => (String::new(), ""),
+        };
+
+        (open, close.to_string())
+    })
+}
+
+pub fn render_markdown(text: &str, entities: &[TextEntity]) -> String {
+    render_with(text, entities, escape_markdown, true, |entity, _| {
+        let (open, close) = match entity.kind {
+            EntityKind::Bold => ("**".to_string(), "**".to_string()),
+            EntityKind::Italic => ("_".to_string(), "_".to_string()),
+            EntityKind::Strike => ("~~".to_string(), "~~".to_string()),
+            EntityKind::Code => ("`".to_string(), "`".to_string()),
+            EntityKind::Pre => (
+                format!("```{}\n", entity.language.as_deref().unwrap_or_default()),
+                "\n```".to_string(),
+            ),
+            EntityKind::TextUrl => (
+                "[".to_string(),
+                format!(
+                    "](<{}>)",
+                    escape_link_target(entity.url.as_deref().unwrap_or_default())
+                ),
+            ),
+            EntityKind::MentionName => match entity.user_id {
+                Some(user_id) => ("[".to_string(), format!("](<tg://user?id={}>)", user_id)),
+                None => (String::new(), String::new()),
+            },
+            _ => (String::new(), String::new()),
+        };
+
+        (open, close)
+    })
+}
+
+/// Walks the text in UTF-16 units, escaping plain segments and wrapping entities with the
+/// markup returned by `markup`. With `verbatim_code` the contents of code and pre blocks are
+/// left unescaped, as Markdown requires.
+fn render_with<E, M>(
+    text: &str,
+    entities: &[TextEntity],
+    escape: E,
+    verbatim_code: bool,
+    markup: M,
+) -> String
+where
+    E: Fn(&str) -> String,
+    M: Fn(&TextEntity, &str) -> (String, String),
+{
+    let units: Vec<u16> = text.encode_utf16().collect();
+    let len = units.len();
+
+    let mut entities: Vec<&TextEntity> = entities
+        .iter()
+        .filter(|entity| entity.offset >= 0 && entity.length > 0)
+        .filter(|entity| (entity.offset + entity.length) as usize <= len)
+        .collect();
+    // Outer entities first, so that inner ones close before them
+    entities.sort_by_key(|entity| (entity.offset, -entity.length));
+
+    let markups: Vec<(String, String)> = entities
+        .iter()
+        .map(|entity| {
+            let start = entity.offset as usize;
+            let end = start + entity.length as usize;
+            markup(entity, &String::from_utf16_lossy(&units[start..end]))
+        })
+        .collect();
+
+    let mut result = String::with_capacity(text.len());
+    let mut open: Vec<usize> = vec![];
+    let mut position = 0;
+    let mut next = 0;
+
+    loop {
+        let verbatim = verbatim_code
+            && open
+                .iter()
+                .any(|&i| matches!(entities[i].kind, EntityKind::Code | EntityKind::Pre));
+
+        let next_open = entities.get(next).map(|entity| entity.offset as usize);
+        let next_close = open
+            .last()
+            .map(|&i| (entities[i].offset + entities[i].length) as usize);
+        let boundary = [next_open, next_close, Some(len)]
+            .into_iter()
+            .flatten()
+            .min()
+            .unwrap()
+            // Improperly nested entities close as soon as they are on top again
+            .max(position);
+
+        let segment = String::from_utf16_lossy(&units[position..boundary]);
+        if verbatim {
+            result.push_str(&segment);
+        } else {
+            result.push_str(&escape(&segment));
+        }
+        position = boundary;
+
+        if next_close.map_or(false, |close| close <= position) {
+            let i = open.pop().unwrap();
+            result.push_str(&markups[i].1);
+        } else if next_open == Some(position) {
+            open.push(next);
+            result.push_str(&markups[next].0);
+            next += 1;
+        } else {
+            break;
+        }
+    }
+
+    result
+}
+
+/// Whether a link target is safe to put into an exported page, which rules out schemes like
+/// `javascript:` that hidden links could use.
+pub(crate) fn is_safe_url(url: &str) -> bool {
+    let Some((scheme, _)) = url.split_once(':') else {
+        return false;
+    };
+
+    ["http", "https", "tg", "mailto"]
+        .iter()
+        .any(|safe| scheme.eq_ignore_ascii_case(safe))
+}
+
+/// Link target of a plain URL in the text, Telegram also detects URLs written without a scheme.
+fn url_entity_target(content: &str) -> Option<String> {
+    if content.contains("://") {
+        Some(content.to_string()).filter(|url| is_safe_url(url))
+    } else {
+        Some(format!("https://{}", content))
+    }
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']' | '~') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Escapes a link target for use between `<` and `>`, where only brackets and line breaks
+/// would end it early.
+pub(crate) fn escape_link_target(url: &str) -> String {
+    let mut escaped = String::with_capacity(url.len());
+    for c in url.chars() {
+        match c {
+            '\\' | '<' | '>' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("%0A"),
+            '\r' => escaped.push_str("%0D"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entity(kind: EntityKind, offset: i32, length: i32) -> TextEntity {
+        TextEntity::new(kind, offset, length)
+    }
+
+    fn text_url(offset: i32, length: i32, url: &str) -> TextEntity {
+        TextEntity {
+            url: Some(url.to_string()),
+            ..entity(EntityKind::TextUrl, offset, length)
+        }
+    }
+
+    #[test]
+    fn offsets_count_surrogate_pairs_as_two_units() {
+        let text = "😀 bold 👍🏽 end";
+        let entities = [
+            entity(EntityKind::Bold, 3, 4),
+            entity(EntityKind::Italic, 8, 4),
+        ];
+
+        assert_eq!(
+            render_html(text, &entities),
+            "😀 <b>bold</b> <i>👍🏽</i> end"
+        );
+        assert_eq!(render_markdown(text, &entities), "😀 **bold** _👍🏽_ end");
+    }
+
+    #[test]
+    fn nested_entities_close_in_order() {
+        let text = "bold italic code";
+        let entities = [
+            entity(EntityKind::Italic, 5, 6),
+            entity(EntityKind::Bold, 0, 16),
+            entity(EntityKind::Code, 12, 4),
+        ];
+
+        assert_eq!(
+            render_html(text, &entities),
+            "<b>bold <i>italic</i> <code>code</code></b>"
+        );
+        assert_eq!(render_markdown(text, &entities), "**bold _italic_ `code`**");
+    }
+
+    #[test]
+    fn overlapping_entities_stay_well_formed() {
+        let entities = [
+            entity(EntityKind::Bold, 0, 4),
+            entity(EntityKind::Italic, 2, 4),
+        ];
+
+        assert_eq!(render_html("abcdef", &entities), "<b>ab<i>cdef</i></b>");
+    }
+
+    #[test]
+    fn entities_out_of_bounds_are_ignored() {
+        let entities = [
+            entity(EntityKind::Bold, 2, 10),
+            entity(EntityKind::Italic, -1, 2),
+        ];
+
+        assert_eq!(render_html("a < b", &entities), "a &lt; b");
+    }
+
+    #[test]
+    fn code_is_verbatim_in_markdown_only() {
+        let entities = [entity(EntityKind::Code, 0, 5)];
+
+        assert_eq!(render_markdown("a*b_c", &entities), "`a*b_c`");
+        assert_eq!(render_markdown("a*b_c", &[]), "a\\*b\\_c");
+        assert_eq!(
+            render_html("a<b>", &[entity(EntityKind::Code, 0, 4)]),
+            "<code>a&lt;b&gt;</code>"
+        );
+    }
+
+    #[test]
+    fn unsafe_link_schemes_are_dropped() {
+        let text = "click here";
+
+        assert_eq!(
+            render_html(text, &[text_url(0, 5, "javascript:alert(1)")]),
+            "click here"
+        );
+        assert_eq!(
+            render_html(text, &[text_url(0, 5, "data:text/html,x")]),
+            "click here"
+        );
+        assert_eq!(
+            render_html(text, &[text_url(0, 5, "HTTPS://example.com/?a=1&b=\"2\"")]),
+            "<a href=\"HTTPS://example.com/?a=1&amp;b=&quot;2&quot;\">click</a> here"
+        );
+        assert_eq!(
+            render_html(text, &[text_url(0, 5, "tg://resolve?domain=durov")]),
+            "<a href=\"tg://resolve?domain=durov\">click</a> here"
+        );
+    }
+
+    #[test]
+    fn plain_urls_get_a_safe_scheme() {
+        assert_eq!(
+            render_html(
+                "see example.com/a?b=1&c=2",
+                &[entity(EntityKind::Url, 4, 21)]
+            ),
+            "see <a href=\"https://example.com/a?b=1&amp;c=2\">example.com/a?b=1&amp;c=2</a>"
+        );
+        assert_eq!(
+            render_html("http://example.com", &[entity(EntityKind::Url, 0, 18)]),
+            "<a href=\"http://example.com\">http://example.com</a>"
+        );
+        assert_eq!(
+            render_html(
+                "javascript://%0Aalert(1)",
+                &[entity(EntityKind::Url, 0, 24)]
+            ),
+            "javascript://%0Aalert(1)"
+        );
+    }
+
+    #[test]
+    fn markdown_link_targets_are_escaped() {
+        let text = "click";
+
+        assert_eq!(
+            render_markdown(text, &[text_url(0, 5, "https://example.com/a_(b) c")]),
+            "[click](<https://example.com/a_(b) c>)"
+        );
+        assert_eq!(
+            render_markdown(text, &[text_url(0, 5, "https://example.com/<x>")]),
+            "[click](<https://example.com/\\<x\\>>)"
+        );
+
+        let mention = TextEntity {
+            user_id: Some(42),
+            ..entity(EntityKind::MentionName, 0, 5)
+        };
+        assert_eq!(
+            render_markdown(text, &[mention]),
+            "[click](<tg://user?id=42>)"
+        );
+    }
+}
@@ -0,0 +1,628 @@
+//! Import of Telegram Desktop exports (`result.json`) made before the archive existed.
+//!
+//! Chat and sender ids in these exports are the same bare ids Telegram uses elsewhere, so
+//! imported messages merge with archived ones. Messages already archived are left untouched.
+
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::bail;
+use chrono::{FixedOffset, NaiveDateTime, TimeZone, Utc};
+use log::{info, warn};
+use sea_orm::ActiveValue;
+use serde::Deserialize;
+use tokio::fs::copy;
+
+use crate::blob::{create_blob_path, sha256_file};
+use crate::chat_kind::ChatKind;
+use crate::db::{Db, MediaState};
+use crate::formatting::{EntityKind, TextEntity};
+use crate::media_data::{MediaData, PollOption};
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Export {
+    Chats {
+        chats: ChatList,
+        /// Chats and channels the account left, which keep their history
+        #[serde(default)]
+        left_chats: ChatList,
+    },
+    Chat(Chat),
+}
+
+#[derive(Default, Deserialize)]
+struct ChatList {
+    list: Vec<Chat>,
+}
+
+#[derive(Deserialize)]
+struct Chat {
+    id: i64,
+    name: Option<String>,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    messages: Vec<Message>,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    id: i32,
+    #[serde(rename = "type")]
+    kind: String,
+    date: String,
+    date_unixtime: Option<String>,
+    edited: Option<String>,
+    edited_unixtime: Option<String>,
+    from: Option<String>,
+    from_id: Option<String>,
+    author: Option<String>,
+    reply_to_message_id: Option<i32>,
+    forwarded_from: Option<String>,
+    photo: Option<String>,
+    file: Option<String>,
+    file_name: Option<String>,
+    media_type: Option<String>,
+    mime_type: Option<String>,
+    file_size: Option<i64>,
+    duration_seconds: Option<i32>,
+    width: Option<i32>,
+    height: Option<i32>,
+    poll: Option<Poll>,
+    location_information: Option<Location>,
+    live_location_period_seconds: Option<i32>,
+    place_name: Option<String>,
+    address: Option<String>,
+    dice: Option<Dice>,
+    contact_information: Option<Contact>,
+    game_title: Option<String>,
+    game_description: Option<String>,
+    #[serde(default)]
+    text: Text,
+    #[serde(default)]
+    text_entities: Vec<TextPart>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(untagged)]
+enum Text {
+    Plain(String),
+    Parts(Vec<TextPartOrString>),
+    #[default]
+    Empty,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TextPartOrString {
+    String(String),
+    Part(TextPart),
+}
+
+#[derive(Clone, Deserialize)]
+struct TextPart {
+    #[serde(rename = "type")]
+    kind: String,
+    text: String,
+    href: Option<String>,
+    user_id: Option<i64>,
+    language: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Poll {
+    question: String,
+    #[serde(default)]
+    closed: bool,
+    total_voters: Option<i32>,
+    #[serde(default)]
+    answers: Vec<PollAnswer>,
+}
+
+#[derive(Deserialize)]
+struct PollAnswer {
+    text: String,
+    voters: Option<i32>,
+    #[serde(default)]
+    chosen: bool,
+}
+
+#[derive(Deserialize)]
+struct Location {
+    latitude: f64,
+    longitude: f64,
+}
+
+#[derive(Deserialize)]
+struct Dice {
+    emoticon: String,
+    value: i32,
+}
+
+#[derive(Deserialize)]
+struct Contact {
+    #[serde(default)]
+    first_name: String,
+    #[serde(default)]
+    last_name: String,
+    #[serde(default)]
+    phone_number: String,
+}
+
+/// Imports a Telegram Desktop export, given as its `result.json` or the folder containing it.
+/// Exports made before Unix timestamps were added need the UTC offset they were made in.
+pub async fn import(
+    db: &Db,
+    media_path: &str,
+    path: &Path,
+    utc_offset: Option<FixedOffset>,
+) -> anyhow::Result<()> {
+    let result_path = if path.is_dir() {
+        path.join("result.json")
+    } else {
+        path.to_path_buf()
+    };
+    let root = result_path
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+
+    let export: Export = serde_json::from_slice(&tokio::fs::read(&result_path).await?)?;
+    let chats = match export {
+        Export::Chats { chats, left_chats } => chats
+            .list
+            .into_iter()
+            .map(|chat| (chat, false))
+            .chain(left_chats.list.into_iter().map(|chat| (chat, true)))
+            .collect(),
+        Export::Chat(chat) => vec![(chat, false)],
+    };
+
+    let has_local_dates = chats
+        .iter()
+        .flat_map(|(chat, _)| &chat.messages)
+        .any(|message| message.date_unixtime.is_none());
+    if has_local_dates && utc_offset.is_none() {
+        bail!(
+            "{} has dates in local time only, pass the UTC offset of the computer that made it \
+             with --utc-offset",
+            result_path.display()
+        );
+    }
+
+    let importer = Importer {
+        db,
+        media_path,
+        root,
+        utc_offset: utc_offset.unwrap_or(FixedOffset::east_opt(0).unwrap()),
+    };
+    for (chat, left) in chats {
+        importer.import_chat(chat, left).await?;
+    }
+
+    Ok(())
+}
+
+struct Importer<'a> {
+    db: &'a Db,
+    media_path: &'a str,
+    root: PathBuf,
+    /// Offset of the local dates in exports without Unix timestamps
+    utc_offset: FixedOffset,
+}
+
+impl Importer<'_> {
+    /// Imports the messages of a chat, `left` marks chats the account is no longer a member of.
+    async fn import_chat(&self, chat: Chat, left: bool) -> anyhow::Result<()> {
+        let kind = get_chat_kind(&chat.kind);
+        let title = chat
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("Chat {}", chat.id));
+        self.db.save_imported_chat(chat.id, kind, &title).await?;
+
+        let archived: HashSet<i32> = self
+            .db
+            .get_message_ids_by_chat(chat.id)
+            .await?
+            .into_iter()
+            .collect();
+        let mut senders = HashSet::new();
+        let mut imported = 0;
+        let mut skipped = 0;
+
+        for message in &chat.messages {
+            // Service messages (joins, pins, calls...) have no counterpart in the archive
+            if message.kind != "message" || archived.contains(&message.id) {
+                skipped += 1;
+                continue;
+            }
+
+            let Some(date) = parse_date(
+                &message.date,
+                message.date_unixtime.as_deref(),
+                self.utc_offset,
+            ) else {
+                warn!(
+                    "Message {} in chat {} has an invalid date {}, skipping...",
+                    message.id, chat.id, message.date
+                );
+                continue;
+            };
+
+            let user_id = match message.from_id.as_deref().and_then(parse_peer_id) {
+                Some((user_id, is_chat)) => {
+                    if senders.insert(user_id) && user_id != chat.id {
+                        let name = message.from.as_deref().unwrap_or_default();
+                        if is_chat {
+                            self.db
+                                .save_imported_chat(user_id, ChatKind::Channel, name)
+                                .await?;
+                        } else {
+                            self.db.save_imported_user(user_id, name).await?;
+                        }
+                    }
+                    user_id
+                }
+                None => chat.id,
+            };
+
+            self.import_message(&chat, kind, left, user_id, message, date)
+                .await?;
+            imported += 1;
+        }
+
+        info!(
+            "Imported {} messages into chat {}, skipped {} already archived or service messages",
+            imported, chat.id, skipped
+        );
+
+        Ok(())
+    }
+
+    async fn import_message(
+        &self,
+        chat: &Chat,
+        kind: ChatKind,
+        left: bool,
+        user_id: i64,
+        message: &Message,
+        date: String,
+    ) -> anyhow::Result<()> {
+        let (text, entities) = get_text(message);
+        let file = message.photo.as_deref().or(message.file.as_deref());
+        let media = match file {
+            Some(file) if file.starts_with("(File not included") => None,
+            Some(file) => match self.export_file(file) {
+                Some(path) if path.is_file() => Some(self.import_media(&path, message).await?),
+                Some(_) => {
+                    warn!(
+                        "Media {} of message {} is missing from the export",
+                        file, message.id
+                    );
+                    None
+                }
+                None => {
+                    warn!(
+                        "Media {} of message {} points outside of the export, ignoring it",
+                        file, message.id
+                    );
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let model = entity::messages::Model {
+            id: message.id,
+            chat_id: chat.id,
+            user_id,
+            text,
+            has_binary_data: file.is_some(),
+            // Media left out of the export is fetched from Telegram like any pending media
+            binary_data_downloaded: media.is_some(),
+            binary_data_path: media.as_ref().and_then(|media| media.path.clone()),
+            binary_data_type: media
+                .as_ref()
+                .and_then(|media| media.path.as_deref())
+                .and_then(get_extension),
+            date,
+            binary_data_reference_expired: false,
+            chat_kind: kind.as_str().to_string(),
+            post_author: message.author.clone(),
+            view_count: None,
+            forward_count: None,
+            edit_date: message.edited.as_deref().and_then(|edited| {
+                parse_date(edited, message.edited_unixtime.as_deref(), self.utc_offset)
+            }),
+            edit_count: 0,
+            media_file_id: None,
+            deleted_at: None,
+            deleted_offline: false,
+            reply_to_message_id: message.reply_to_message_id,
+            forward_from_id: None,
+            forward_from_name: message.forwarded_from.clone(),
+            forward_date: None,
+            forward_message_id: None,
+            entities: Some(entities)
+                .filter(|entities| !entities.is_empty())
+                .and_then(|entities| serde_json::to_string(&entities).ok()),
+            // Left chats can't be fetched from, `backfill-skipped` queues it after rejoining
+            binary_data_skipped: left && file.is_some() && media.is_none(),
+            media_id: None,
+            grouped_id: None,
+            media_data: get_media_data(message).and_then(|data| serde_json::to_string(&data).ok()),
+            web_page: None,
+            binary_data_attempted_at: None,
+        };
+
+        if self.db.save_imported_message(model.clone()).await? {
+            if let Some(media) = media {
+                self.db.attach_message_media(model, &media).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Path of a file referenced by the export, `None` if it points outside of the export.
+    fn export_file(&self, file: &str) -> Option<PathBuf> {
+        let inside = Path::new(file)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+
+        inside.then(|| self.root.join(file))
+    }
+
+    /// Copies an exported file into the blob store, sharing blobs with identical content.
+    async fn import_media(
+        &self,
+        path: &Path,
+        message: &Message,
+    ) -> anyhow::Result<entity::media::Model> {
+        let path = path.to_string_lossy().to_string();
+        let sha256 = sha256_file(&path).await?;
+        if let Some(existing) = self.db.get_downloaded_media_by_sha256(&sha256).await? {
+            return Ok(existing);
+        }
+
+        let extension = get_extension(&path).unwrap_or_default();
+        let blob_path = create_blob_path(self.media_path, &sha256, &extension).await?;
+        copy(&path, &blob_path).await?;
+        let size = tokio::fs::metadata(&blob_path).await?.len() as i64;
+
+        let model = entity::media::ActiveModel {
+            id: ActiveValue::NotSet,
+            file_key: ActiveValue::Set(None),
+            file_id: ActiveValue::Set(None),
+            kind: ActiveValue::Set(Some(get_media_kind(message).to_string())),
+            mime_type: ActiveValue::Set(message.mime_type.clone()),
+            file_name: ActiveValue::Set(message.file_name.clone()),
+            size: ActiveValue::Set(Some(message.file_size.unwrap_or(size))),
+            width: ActiveValue::Set(message.width),
+            height: ActiveValue::Set(message.height),
+            duration: ActiveValue::Set(message.duration_seconds),
+            sha256: ActiveValue::Set(Some(sha256)),
+            path: ActiveValue::Set(Some(blob_path)),
+            state: ActiveValue::Set(MediaState::Downloaded.as_str().to_string()),
+            attempts: ActiveValue::Set(0),
+            last_error: ActiveValue::Set(None),
+            ref_count: ActiveValue::Set(0),
+        };
+
+        self.db.insert_media(model).await
+    }
+}
+
+fn get_chat_kind(kind: &str) -> ChatKind {
+    match kind {
+        "private_group" => ChatKind::Group,
+        "public_supergroup" | "private_supergroup" => ChatKind::Supergroup,
+        "public_channel" | "private_channel" => ChatKind::Channel,
+        _ => ChatKind::User,
+    }
+}
+
+fn get_media_kind(message: &Message) -> &'static str {
+    if message.photo.is_some() {
+        return "photo";
+    }
+
+    match message.media_type.as_deref() {
+        Some("sticker") => "sticker",
+        Some("animation") => "animation",
+        Some("video_file") => "video",
+        Some("video_message") => "round_video",
+        Some("voice_message") => "voice",
+        Some("audio_file") => "audio",
+        _ => "document",
+    }
+}
+
+/// Splits `user123` or `channel123` into the id and whether it is a chat.
+fn parse_peer_id(from_id: &str) -> Option<(i64, bool)> {
+    if let Some(id) = from_id.strip_prefix("user") {
+        return id.parse().ok().map(|id| (id, false));
+    }
+
+    from_id
+        .strip_prefix("channel")
+        .or_else(|| from_id.strip_prefix("chat"))
+        .and_then(|id| id.parse().ok())
+        .map(|id| (id, true))
+}
+
+/// Prefers the Unix timestamp, the plain date is in the local time of the exporting computer.
+fn parse_date(date: &str, unixtime: Option<&str>, utc_offset: FixedOffset) -> Option<String> {
+    let date = match unixtime.and_then(|unixtime| unixtime.parse().ok()) {
+        Some(unixtime) => Utc.timestamp_opt(unixtime, 0).single()?,
+        None => {
+            let date = NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S").ok()?;
+            utc_offset
+                .from_local_datetime(&date)
+                .single()?
+                .with_timezone(&Utc)
+        }
+    };
+
+    Some(date.to_string())
+}
+
+fn get_extension(path: &str) -> Option<String> {
+    Path::new(path)
+        .extension()
+        .and_then(OsStr::to_str)
+        .map(|extension| format!(".{}", extension))
+}
+
+/// Rebuilds the text and its entities from the exported parts. Older exports only have
+/// `text`, newer ones also have the flat `text_entities`.
+fn get_text(message: &Message) -> (String, Vec<TextEntity>) {
+    let parts = if !message.text_entities.is_empty() {
+        message.text_entities.clone()
+    } else {
+        match &message.text {
+            Text::Plain(text) => return (text.clone(), vec![]),
+            Text::Parts(parts) => parts
+                .iter()
+                .map(|part| match part {
+                    TextPartOrString::String(text) => TextPart {
+                        kind: "plain".to_string(),
+                        text: text.clone(),
+                        href: None,
+                        user_id: None,
+                        language: None,
+                    },
+                    TextPartOrString::Part(part) => part.clone(),
+                })
+                .collect(),
+            Text::Empty => vec![],
+        }
+    };
+
+    let mut text = String::new();
+    let mut entities = vec![];
+    let mut offset = 0;
+    for part in parts {
+        let length = part.text.encode_utf16().count() as i32;
+        text.push_str(&part.text);
+
+        if let Some(kind) = get_entity_kind(&part.kind) {
+            entities.push(TextEntity {
+                kind,
+                offset,
+                length,
+                url: part.href,
+                user_id: part.user_id,
+                language: part.language.filter(|language| !language.is_empty()),
+            });
+        }
+        offset += length;
+    }
+
+    (text, entities)
+}
+
+fn get_entity_kind(kind: &str) -> Option<EntityKind> {
+    let kind = match kind {
+        "plain" => return None,
+        "bold" => EntityKind::Bold,
+        "italic" => EntityKind::Italic,
+        "underline" => EntityKind::Underline,
+        "strikethrough" => EntityKind::Strike,
+        "spoiler" => EntityKind::Spoiler,
+        "code" => EntityKind::Code,
+        "pre" => EntityKind::Pre,
+        "text_link" => EntityKind::TextUrl,
+        "link" => EntityKind::Url,
+        "email" => EntityKind::Email,
+        "phone" => EntityKind::Phone,
+        "mention" => EntityKind::Mention,
+        "mention_name" => EntityKind::MentionName,
+        "hashtag" => EntityKind::Hashtag,
+        "cashtag" => EntityKind::Cashtag,
+        "bot_command" => EntityKind::BotCommand,
+        "bank_card" => EntityKind::BankCard,
+        "blockquote" => EntityKind::Blockquote,
+        "custom_emoji" => EntityKind::CustomEmoji,
+        _ => EntityKind::Unknown,
+    };
+
+    Some(kind)
+}
+
+fn get_media_data(message: &Message) -> Option<MediaData> {
+    if let Some(poll) = &message.poll {
+        return Some(MediaData::Poll {
+            question: poll.question.clone(),
+            options: poll
+                .answers
+                .iter()
+                .map(|answer| PollOption {
+                    text: answer.text.clone(),
+                    voters: answer.voters,
+                    chosen: answer.chosen,
+                    correct: false,
+                })
+                .collect(),
+            closed: poll.closed,
+            quiz: false,
+            multiple_choice: false,
+            total_voters: poll.total_voters,
+        });
+    }
+
+    if let Some(place_name) = &message.place_name {
+        let location = message.location_information.as_ref();
+        return Some(MediaData::Venue {
+            latitude: location.map(|location| location.latitude),
+            longitude: location.map(|location| location.longitude),
+            title: place_name.clone(),
+            address: message.address.clone().unwrap_or_default(),
+            provider: String::new(),
+            venue_id: String::new(),
+            venue_type: String::new(),
+        });
+    }
+
+    if let Some(location) = &message.location_information {
+        return Some(match message.live_location_period_seconds {
+            Some(period) => MediaData::LiveLocation {
+                latitude: location.latitude,
+                longitude: location.longitude,
+                period,
+                heading: None,
+            },
+            None => MediaData::Geo {
+                latitude: location.latitude,
+                longitude: location.longitude,
+                accuracy_radius: None,
+            },
+        });
+    }
+
+    if let Some(dice) = &message.dice {
+        return Some(MediaData::Dice {
+            emoticon: dice.emoticon.clone(),
+            value: dice.value,
+        });
+    }
+
+    if let Some(contact) = &message.contact_information {
+        return Some(MediaData::Contact {
+            phone_number: contact.phone_number.clone(),
+            first_name: contact.first_name.clone(),
+            last_name: contact.last_name.clone(),
+            user_id: None,
+            vcard: String::new(),
+        });
+    }
+
+    message.game_title.as_ref().map(|title| MediaData::Game {
+        short_name: String::new(),
+        title: title.clone(),
+        description: message.game_description.clone().unwrap_or_default(),
+    })
+}
@@ -1,28 +1,117 @@
+mod album;
+mod blob;
 mod bot;
+mod chat_kind;
+mod cli;
 mod config;
 mod db;
+mod export;
+mod formatting;
+mod import;
+mod media_data;
+mod rules;
+mod web_page;
 
+use crate::album::group_posts;
 use crate::bot::Bot;
+use crate::cli::{Cli, Command};
 use crate::config::Config;
-use crate::db::Db;
+use crate::db::{Db, MessageSearch};
+use crate::export::ExportFilter;
+use crate::formatting::{entities_from_json, render, Format};
+use crate::media_data::media_data_from_json;
+use crate::web_page::web_page_from_json;
+use clap::Parser;
 use dotenvy::dotenv;
 use log::{error, info};
+use std::io::IsTerminal;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenv().ok();
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
 
+    let cli = Cli::parse();
+
     let config = Config::init();
-    let db = Db::init(config.database_url).await;
-    let bot = Bot::init(
+    let db = Db::init(config.database_url.clone()).await;
+
+    match cli.command.unwrap_or(Command::Run) {
+        Command::Run => run(config, db).await,
+        Command::Deleted { chat_id } => list_deleted_messages(&db, chat_id).await,
+        Command::Reconcile { chat_ids } => reconcile(config, db, &chat_ids).await,
+        Command::BackfillSkipped { chat_ids } => {
+            let count = db.reset_skipped_media(&chat_ids).await?;
+            println!("Queued {} skipped media for download.", count);
+            Ok(())
+        }
+        Command::RetryExpired { chat_ids } => retry_expired_media(config, db, &chat_ids).await,
+        Command::History {
+            chat_id,
+            message_id,
+            format,
+        } => show_message_history(&db, chat_id, message_id, format).await,
+        Command::Export {
+            chat_ids,
+            output,
+            format,
+            since,
+            until,
+            senders,
+        } => {
+            let filter = ExportFilter {
+                since,
+                until,
+                senders,
+            };
+            export::export(&db, &chat_ids, format, &filter, &output).await
+        }
+        Command::Import { path, utc_offset } => {
+            import::import(&db, &config.media_path, &path, utc_offset).await
+        }
+        Command::Search {
+            query,
+            chat_ids,
+            senders,
+            since,
+            until,
+            limit,
+        } => {
+            // Matches are shown in bold, or marked with asterisks when piped
+            let highlight = if std::io::stdout().is_terminal() {
+                ("\x1b[1m", "\x1b[0m")
+            } else {
+                ("*", "*")
+            };
+            let search = MessageSearch {
+                query: &query,
+                chat_ids: &chat_ids,
+                senders: &senders,
+                since,
+                until,
+                limit,
+                highlight,
+            };
+            search_messages(&db, search).await
+        }
+    }
+}
+
+async fn init_bot(config: Config, db: Db) -> anyhow::Result<Bot> {
+    Bot::init(
         config.api_id,
         config.api_hash,
         config.teledump_session_path,
         config.media_path,
+        config.rules,
+        config.download_workers,
         db,
     )
-    .await?;
+    .await
+}
+
+async fn run(config: Config, db: Db) -> anyhow::Result<()> {
+    let bot = init_bot(config, db).await?;
 
     tokio::select! {
         _ = tokio::signal::ctrl_c() => {
@@ -40,3 +129,165 @@ async fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+async fn list_deleted_messages(db: &Db, chat_id: i64) -> anyhow::Result<()> {
+    let messages = db.get_deleted_messages_by_chat(chat_id).await?;
+    let count = messages.len();
+
+    for post in group_posts(messages) {
+        let message = post.first();
+        let caption = post.caption();
+        println!(
+            "[{}] #{}{} from {} (deleted {}): {}",
+            message.date,
+            message.id,
+            if post.is_album() {
+                format!(" (album of {})", post.messages.len())
+            } else {
+                String::new()
+            },
+            message.user_id,
+            message.deleted_at.as_deref().unwrap_or_default(),
+            if caption.text.is_empty() {
+                media_data_from_json(caption.media_data.as_deref())
+                    .map(|media_data| media_data.summary())
+                    .unwrap_or_default()
+            } else {
+                caption.text.clone()
+            }
+        );
+    }
+
+    println!("{} deleted message(s) in chat {}.", count, chat_id);
+
+    Ok(())
+}
+
+async fn search_messages(db: &Db, search: MessageSearch<'_>) -> anyhow::Result<()> {
+    let results = db.search_messages(&search).await?;
+    for result in &results {
+        println!(
+            "[{}] chat {} #{} from {}{}: {}",
+            result.date,
+            result.chat_id,
+            result.id,
+            result.user_id,
+            if result.deleted_at.is_some() {
+                " (deleted)"
+            } else {
+                ""
+            },
+            result.snippet.replace('\n', " ")
+        );
+    }
+
+    println!("{} result(s).", results.len());
+
+    Ok(())
+}
+
+async fn reconcile(config: Config, db: Db, chat_ids: &[i64]) -> anyhow::Result<()> {
+    let bot = init_bot(config, db).await?;
+
+    let reports = bot.reconcile_chats(chat_ids).await?;
+    for report in &reports {
+        if !report.in_dialogs {
+            println!(
+                "Chat {} ({}): not in dialogs anymore, {} archived messages left untouched.",
+                report.chat_name, report.chat_id, report.local_messages
+            );
+            continue;
+        }
+
+        println!(
+            "Chat {} ({}): {} on server, {} archived, {} marked as deleted while offline.",
+            report.chat_name,
+            report.chat_id,
+            report.server_messages,
+            report.local_messages,
+            report.marked_deleted
+        );
+    }
+
+    bot.save_session()?;
+
+    Ok(())
+}
+
+async fn retry_expired_media(config: Config, db: Db, chat_ids: &[i64]) -> anyhow::Result<()> {
+    let bot = init_bot(config, db).await?;
+
+    let recovered = bot.retry_expired_media(chat_ids).await?;
+    println!(
+        "Recovered {} media with expired file references.",
+        recovered
+    );
+
+    bot.save_session()?;
+
+    Ok(())
+}
+
+async fn show_message_history(
+    db: &Db,
+    chat_id: i64,
+    message_id: i32,
+    format: Format,
+) -> anyhow::Result<()> {
+    let Some(message) = db.get_message(chat_id, message_id).await? else {
+        anyhow::bail!("Message {} in chat {} is not archived", message_id, chat_id);
+    };
+
+    for revision in db.get_message_revisions(message_id, chat_id).await? {
+        let entities = entities_from_json(revision.entities.as_deref());
+        println!(
+            "--- version {} ({}){}",
+            revision.revision,
+            revision.date,
+            if revision.media_changed {
+                ", media replaced"
+            } else {
+                ""
+            }
+        );
+        println!("{}", render(&revision.text, &entities, format));
+    }
+
+    if let Some(grouped_id) = message.grouped_id {
+        let album = db.get_album_messages(chat_id, grouped_id).await?;
+        println!(
+            "--- part of an album of {} messages: {}",
+            album.len(),
+            album
+                .iter()
+                .map(|message| format!("#{}", message.id))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    let entities = entities_from_json(message.entities.as_deref());
+    println!(
+        "--- current version {} ({})",
+        message.edit_count,
+        message.edit_date.as_deref().unwrap_or(&message.date)
+    );
+    println!("{}", render(&message.text, &entities, format));
+
+    if let Some(preview) = web_page_from_json(message.web_page.as_deref()) {
+        println!(
+            "--- link preview: {} ({})",
+            preview
+                .title
+                .or(preview.site_name)
+                .unwrap_or(preview.display_url),
+            preview.url
+        );
+    }
+
+    if let Some(deleted_at) = message.deleted_at {
+        println!("--- deleted at {}", deleted_at);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,214 @@
+//! Media without a file to download, kept as structured data instead: polls, locations,
+//! venues, dice, contact cards and games.
+
+use grammers_client::types::Message;
+use grammers_tl_types as tl;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PollOption {
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub voters: Option<i32>,
+    #[serde(default)]
+    pub chosen: bool,
+    #[serde(default)]
+    pub correct: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MediaData {
+    Poll {
+        question: String,
+        options: Vec<PollOption>,
+        closed: bool,
+        quiz: bool,
+        multiple_choice: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        total_voters: Option<i32>,
+    },
+    Geo {
+        latitude: f64,
+        longitude: f64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        accuracy_radius: Option<i32>,
+    },
+    LiveLocation {
+        latitude: f64,
+        longitude: f64,
+        /// How long the location is shared for, in seconds
+        period: i32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        heading: Option<i32>,
+    },
+    Venue {
+        latitude: Option<f64>,
+        longitude: Option<f64>,
+        title: String,
+        address: String,
+        provider: String,
+        venue_id: String,
+        venue_type: String,
+    },
+    Dice {
+        emoticon: String,
+        value: i32,
+    },
+    Contact {
+        phone_number: String,
+        first_name: String,
+        last_name: String,
+        user_id: Option<i64>,
+        vcard: String,
+    },
+    Game {
+        short_name: String,
+        title: String,
+        description: String,
+    },
+}
+
+impl MediaData {
+    pub fn from_message(message: &Message) -> Option<Self> {
+        use tl::enums::MessageMedia as M;
+
+        match message.raw.media.as_ref()? {
+            M::Poll(media) => {
+                let tl::enums::Poll::Poll(poll) = &media.poll;
+                let tl::enums::PollResults::Results(results) = &media.results;
+                let voters = results.results.as_deref().unwrap_or_default();
+
+                let options = poll
+                    .answers
+                    .iter()
+                    .map(|tl::enums::PollAnswer::Answer(answer)| {
+                        let voters = voters
+                            .iter()
+                            .map(|tl::enums::PollAnswerVoters::Voters(voters)| voters)
+                            .find(|voters| voters.option == answer.option);
+
+                        PollOption {
+                            text: answer.text.clone(),
+                            voters: voters.map(|voters| voters.voters),
+                            chosen: voters.map_or(false, |voters| voters.chosen),
+                            correct: voters.map_or(false, |voters| voters.correct),
+                        }
+                    })
+                    .collect();
+
+                Some(MediaData::Poll {
+                    question: poll.question.clone(),
+                    options,
+                    closed: poll.closed,
+                    quiz: poll.quiz,
+                    multiple_choice: poll.multiple_choice,
+                    total_voters: results.total_voters,
+                })
+            }
+            M::Geo(media) => {
+                let tl::enums::GeoPoint::Point(point) = &media.geo else {
+                    return None;
+                };
+
+                Some(MediaData::Geo {
+                    latitude: point.lat,
+                    longitude: point.long,
+                    accuracy_radius: point.accuracy_radius,
+                })
+            }
+            M::GeoLive(media) => {
+                let tl::enums::GeoPoint::Point(point) = &media.geo else {
+                    return None;
+                };
+
+                Some(MediaData::LiveLocation {
+                    latitude: point.lat,
+                    longitude: point.long,
+                    period: media.period,
+                    heading: media.heading,
+                })
+            }
+            M::Venue(media) => {
+                let point = match &media.geo {
+                    tl::enums::GeoPoint::Point(point) => Some(point),
+                    tl::enums::GeoPoint::Empty => None,
+                };
+
+                Some(MediaData::Venue {
+                    latitude: point.map(|point| point.lat),
+                    longitude: point.map(|point| point.long),
+                    title: media.title.clone(),
+                    address: media.address.clone(),
+                    provider: media.provider.clone(),
+                    venue_id: media.venue_id.clone(),
+                    venue_type: media.venue_type.clone(),
+                })
+            }
+            M::Dice(media) => Some(MediaData::Dice {
+                emoticon: media.emoticon.clone(),
+                value: media.value,
+            }),
+            M::Contact(media) => Some(MediaData::Contact {
+                phone_number: media.phone_number.clone(),
+                first_name: media.first_name.clone(),
+                last_name: media.last_name.clone(),
+                user_id: Some(media.user_id).filter(|user_id| *user_id != 0),
+                vcard: media.vcard.clone(),
+            }),
+            M::Game(media) => {
+                let tl::enums::Game::Game(game) = &media.game;
+
+                Some(MediaData::Game {
+                    short_name: game.short_name.clone(),
+                    title: game.title.clone(),
+                    description: game.description.clone(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// One line description, used where the message has no text of its own.
+    pub fn summary(&self) -> String {
+        match self {
+            MediaData::Poll {
+                question, options, ..
+            } => format!("[poll] {} ({} options)", question, options.len()),
+            MediaData::Geo {
+                latitude,
+                longitude,
+                ..
+            } => format!("[location] {}, {}", latitude, longitude),
+            MediaData::LiveLocation {
+                latitude,
+                longitude,
+                ..
+            } => format!("[live location] {}, {}", latitude, longitude),
+            MediaData::Venue { title, address, .. } => format!("[venue] {}, {}", title, address),
+            MediaData::Dice { emoticon, value } => format!("[dice] {} {}", emoticon, value),
+            MediaData::Contact {
+                phone_number,
+                first_name,
+                last_name,
+                ..
+            } => {
+                let name = [first_name.as_str(), last_name.as_str()]
+                    .into_iter()
+                    .filter(|part| !part.is_empty())
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                format!("[contact] {} {}", name, phone_number)
+            }
+            MediaData::Game { title, .. } => format!("[game] {}", title),
+        }
+    }
+}
+
+pub fn media_data_to_json(message: &Message) -> Option<String> {
+    MediaData::from_message(message).and_then(|data| serde_json::to_string(&data).ok())
+}
+
+pub fn media_data_from_json(media_data: Option<&str>) -> Option<MediaData> {
+    media_data.and_then(|media_data| serde_json::from_str(media_data).ok())
+}
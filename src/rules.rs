@@ -0,0 +1,424 @@
+//! Rules deciding which chats get archived and which of their media is downloaded.
+//!
+//! Rules are read from a JSON file, e.g.:
+//!
+//! ```json
+//! {
+//!     "chats": {
+//!         "default": "allow",
+//!         "rules": [
+//!             { "action": "deny", "kind": "channel" },
+//!             { "action": "allow", "username": "rustlang" },
+//!             { "action": "deny", "title": "(?i)spam" }
+//!         ]
+//!     },
+//!     "media": {
+//!         "default": "allow",
+//!         "rules": [{ "action": "deny", "id": 777000 }]
+//!     },
+//!     "downloads": [
+//!         { "media_kind": "video", "max_size": 104857600 },
+//!         { "action": "deny", "mime": "^audio/", "kind": "channel" }
+//!     ]
+//! }
+//! ```
+//!
+//! The first rule whose selectors all match the chat wins, otherwise the default action applies.
+//! Download policies work the same way, everything not matched by a policy is downloaded.
+
+use std::fs;
+use std::path::Path;
+
+use grammers_client::types::Chat;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::chat_kind::ChatKind;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    #[default]
+    Allow,
+    Deny,
+}
+
+/// What rules look at when matching a chat.
+struct ChatInfo<'a> {
+    id: i64,
+    username: Option<&'a str>,
+    kind: ChatKind,
+    title: &'a str,
+}
+
+impl<'a> From<&'a Chat> for ChatInfo<'a> {
+    fn from(chat: &'a Chat) -> Self {
+        ChatInfo {
+            id: chat.id(),
+            username: chat.username(),
+            kind: ChatKind::from(chat),
+            title: chat.name(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ChatSelector {
+    id: Option<i64>,
+    username: Option<String>,
+    kind: Option<ChatKind>,
+    title: Option<Regex>,
+}
+
+impl ChatSelector {
+    fn new(
+        id: Option<i64>,
+        username: Option<String>,
+        kind: Option<ChatKind>,
+        title: Option<String>,
+    ) -> Result<Self, String> {
+        let title = title
+            .map(|title| Regex::new(&title).map_err(|e| e.to_string()))
+            .transpose()?;
+
+        Ok(ChatSelector {
+            id,
+            username,
+            kind,
+            title,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.id.is_none() && self.username.is_none() && self.kind.is_none() && self.title.is_none()
+    }
+
+    fn matches(&self, chat: &ChatInfo) -> bool {
+        if let Some(id) = self.id {
+            if chat.id != id {
+                return false;
+            }
+        }
+
+        if let Some(username) = &self.username {
+            let username = username.trim_start_matches('@');
+            match chat.username {
+                Some(chat_username) if chat_username.eq_ignore_ascii_case(username) => {}
+                _ => return false,
+            }
+        }
+
+        if let Some(kind) = self.kind {
+            if chat.kind != kind {
+                return false;
+            }
+        }
+
+        if let Some(title) = &self.title {
+            if !title.is_match(chat.title) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// A rule as written in the rules file. Unknown keys are rejected, a misspelled selector
+/// would otherwise leave a rule that matches every chat.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRule {
+    action: Action,
+    id: Option<i64>,
+    username: Option<String>,
+    kind: Option<ChatKind>,
+    title: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "RawRule")]
+pub struct Rule {
+    action: Action,
+    chat: ChatSelector,
+}
+
+impl TryFrom<RawRule> for Rule {
+    type Error = String;
+
+    fn try_from(rule: RawRule) -> Result<Self, Self::Error> {
+        let chat = ChatSelector::new(rule.id, rule.username, rule.kind, rule.title)?;
+        if chat.is_empty() {
+            return Err("rule needs at least one of id, username, kind or title".to_string());
+        }
+
+        Ok(Rule {
+            action: rule.action,
+            chat,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleSet {
+    #[serde(default)]
+    default: Action,
+    #[serde(default)]
+    rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    fn action_for(&self, chat: &ChatInfo) -> Action {
+        self.rules
+            .iter()
+            .find(|rule| rule.chat.matches(chat))
+            .map(|rule| rule.action)
+            .unwrap_or(self.default)
+    }
+}
+
+/// What is known about a piece of media before downloading it.
+pub struct MediaInfo<'a> {
+    pub kind: &'a str,
+    pub mime_type: Option<&'a str>,
+    pub size: Option<i64>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawMediaPolicy {
+    #[serde(default)]
+    action: Action,
+    id: Option<i64>,
+    username: Option<String>,
+    kind: Option<ChatKind>,
+    title: Option<String>,
+    media_kind: Option<String>,
+    mime: Option<String>,
+    max_size: Option<i64>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "RawMediaPolicy")]
+pub struct MediaPolicy {
+    action: Action,
+    chat: ChatSelector,
+    media_kind: Option<String>,
+    mime: Option<Regex>,
+    /// Media larger than this many bytes is skipped even if the policy allows it
+    max_size: Option<i64>,
+}
+
+impl TryFrom<RawMediaPolicy> for MediaPolicy {
+    type Error = String;
+
+    fn try_from(policy: RawMediaPolicy) -> Result<Self, Self::Error> {
+        let chat = ChatSelector::new(policy.id, policy.username, policy.kind, policy.title)?;
+        let mime = policy
+            .mime
+            .map(|mime| Regex::new(&mime).map_err(|e| e.to_string()))
+            .transpose()?;
+        if chat.is_empty()
+            && policy.media_kind.is_none()
+            && mime.is_none()
+            && policy.max_size.is_none()
+        {
+            return Err("download policy needs at least one selector or a max_size".to_string());
+        }
+
+        Ok(MediaPolicy {
+            action: policy.action,
+            chat,
+            media_kind: policy.media_kind,
+            mime,
+            max_size: policy.max_size,
+        })
+    }
+}
+
+impl MediaPolicy {
+    fn matches(&self, chat: &ChatInfo, media: &MediaInfo) -> bool {
+        if let Some(media_kind) = &self.media_kind {
+            if media_kind != media.kind {
+                return false;
+            }
+        }
+
+        if let Some(mime) = &self.mime {
+            if !mime.is_match(media.mime_type.unwrap_or_default()) {
+                return false;
+            }
+        }
+
+        self.chat.matches(chat)
+    }
+
+    fn allows(&self, media: &MediaInfo) -> bool {
+        match (self.action, self.max_size, media.size) {
+            (Action::Deny, _, _) => false,
+            (Action::Allow, Some(max_size), Some(size)) => size <= max_size,
+            (Action::Allow, _, _) => true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rules {
+    #[serde(default)]
+    chats: RuleSet,
+    #[serde(default)]
+    media: RuleSet,
+    #[serde(default)]
+    downloads: Vec<MediaPolicy>,
+}
+
+impl Rules {
+    /// Loads rules from `path`, allowing everything if the file does not exist.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(Rules::default());
+        }
+
+        let rules = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&rules)?)
+    }
+
+    pub fn is_chat_allowed(&self, chat: &Chat) -> bool {
+        self.chats.action_for(&ChatInfo::from(chat)) == Action::Allow
+    }
+
+    pub fn is_media_allowed(&self, chat: &Chat) -> bool {
+        self.is_chat_allowed(chat) && self.media.action_for(&ChatInfo::from(chat)) == Action::Allow
+    }
+
+    pub fn is_download_allowed(&self, chat: &Chat, media: &MediaInfo) -> bool {
+        self.download_policy_allows(&ChatInfo::from(chat), media)
+    }
+
+    fn download_policy_allows(&self, chat: &ChatInfo, media: &MediaInfo) -> bool {
+        self.downloads
+            .iter()
+            .find(|policy| policy.matches(chat, media))
+            .map_or(true, |policy| policy.allows(media))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chat<'a>(
+        id: i64,
+        username: Option<&'a str>,
+        kind: ChatKind,
+        title: &'a str,
+    ) -> ChatInfo<'a> {
+        ChatInfo {
+            id,
+            username,
+            kind,
+            title,
+        }
+    }
+
+    fn parse(rules: &str) -> Result<Rules, serde_json::Error> {
+        serde_json::from_str(rules)
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules = parse(
+            r#"{
+                "chats": {
+                    "default": "allow",
+                    "rules": [
+                        { "action": "allow", "username": "@RustLang" },
+                        { "action": "deny", "kind": "channel" },
+                        { "action": "deny", "title": "(?i)spam" }
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let action = |chat: &ChatInfo| rules.chats.action_for(chat);
+        assert_eq!(
+            action(&chat(1, Some("rustlang"), ChatKind::Channel, "Rust")),
+            Action::Allow
+        );
+        assert_eq!(
+            action(&chat(2, None, ChatKind::Channel, "News")),
+            Action::Deny
+        );
+        assert_eq!(
+            action(&chat(3, None, ChatKind::Group, "Daily SPAM")),
+            Action::Deny
+        );
+        assert_eq!(
+            action(&chat(4, None, ChatKind::Group, "Friends")),
+            Action::Allow
+        );
+    }
+
+    #[test]
+    fn all_selectors_of_a_rule_must_match() {
+        let rules = parse(
+            r#"{ "chats": { "default": "allow", "rules": [
+                { "action": "deny", "id": 5, "kind": "group" }
+            ] } }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            rules.chats.action_for(&chat(5, None, ChatKind::Group, "")),
+            Action::Deny
+        );
+        assert_eq!(
+            rules.chats.action_for(&chat(5, None, ChatKind::User, "")),
+            Action::Allow
+        );
+    }
+
+    #[test]
+    fn download_policies_limit_size() {
+        let rules = parse(
+            r#"{ "downloads": [
+                { "media_kind": "video", "max_size": 100 },
+                { "action": "deny", "mime": "^audio/" }
+            ] }"#,
+        )
+        .unwrap();
+        let group = chat(1, None, ChatKind::Group, "");
+        let media = |kind, mime_type, size| MediaInfo {
+            kind,
+            mime_type,
+            size,
+        };
+
+        assert!(rules.download_policy_allows(&group, &media("video", None, Some(100))));
+        assert!(!rules.download_policy_allows(&group, &media("video", None, Some(101))));
+        assert!(!rules.download_policy_allows(&group, &media("audio", Some("audio/ogg"), None)));
+        assert!(rules.download_policy_allows(&group, &media("photo", Some("image/jpeg"), None)));
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(
+            parse(r#"{ "chats": { "rules": [{ "action": "deny", "usrname": "foo" }] } }"#).is_err()
+        );
+        assert!(parse(r#"{ "downloads": [{ "media_kind": "video", "maxsize": 1 }] }"#).is_err());
+        assert!(parse(r#"{ "chats": { "defualt": "deny" } }"#).is_err());
+        assert!(parse(r#"{ "chat": {} }"#).is_err());
+    }
+
+    #[test]
+    fn rules_without_selectors_are_rejected() {
+        assert!(parse(r#"{ "chats": { "rules": [{ "action": "deny" }] } }"#).is_err());
+        assert!(parse(r#"{ "downloads": [{ "action": "deny" }] }"#).is_err());
+        assert!(
+            parse(r#"{ "chats": { "rules": [{ "action": "deny", "title": "(" }] } }"#).is_err()
+        );
+    }
+}
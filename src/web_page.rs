@@ -0,0 +1,70 @@
+//! Link previews attached to messages.
+
+use grammers_client::types::{Media, Message};
+use grammers_tl_types as tl;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebPagePreview {
+    pub url: String,
+    pub display_url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub site_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub has_photo: bool,
+}
+
+fn get_web_page(message: &Message) -> Option<&tl::types::WebPage> {
+    match message.raw.media.as_ref()? {
+        tl::enums::MessageMedia::WebPage(media) => match &media.webpage {
+            tl::enums::WebPage::Page(page) => Some(page),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+pub fn web_page_to_json(message: &Message) -> Option<String> {
+    let page = get_web_page(message)?;
+
+    let preview = WebPagePreview {
+        url: page.url.clone(),
+        display_url: page.display_url.clone(),
+        kind: page.r#type.clone(),
+        site_name: page.site_name.clone(),
+        title: page.title.clone(),
+        description: page.description.clone(),
+        author: page.author.clone(),
+        has_photo: page.photo.is_some(),
+    };
+
+    serde_json::to_string(&preview).ok()
+}
+
+pub fn web_page_from_json(web_page: Option<&str>) -> Option<WebPagePreview> {
+    web_page.and_then(|web_page| serde_json::from_str(web_page).ok())
+}
+
+/// The preview photo as regular photo media, so it can go through the media pipeline.
+pub fn get_web_page_photo(message: &Message) -> Option<Media> {
+    let photo = get_web_page(message)?.photo.clone()?;
+
+    let mut message = message.clone();
+    message.raw.media = Some(tl::enums::MessageMedia::Photo(
+        tl::types::MessageMediaPhoto {
+            spoiler: false,
+            photo: Some(photo),
+            ttl_seconds: None,
+        },
+    ));
+
+    message.media()
+}